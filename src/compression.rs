@@ -1,6 +1,8 @@
 use anyhow::{Result, bail};
 use flate2::read::ZlibDecoder;
+use std::fmt;
 use std::io::Read;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CompressionMethod {
@@ -22,40 +24,152 @@ impl CompressionMethod {
     }
 }
 
-pub fn decompress(data: &[u8], method: CompressionMethod, expected_size: usize) -> Result<Vec<u8>> {
+impl FromStr for CompressionMethod {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "zlib" => Ok(Self::Zlib),
+            "lz4" => Ok(Self::Lz4),
+            "zstd" => Ok(Self::Zstd),
+            _ => bail!("Unknown compression method '{}' (expected none, zlib, lz4 or zstd)", s),
+        }
+    }
+}
+
+/// Compression level, stored in the high nibble of the compression flags.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CompressionLevel {
+    Fast,
+    #[default]
+    Default,
+    Max,
+}
+
+impl CompressionLevel {
+    const FAST_FLAG: u8 = 0x10;
+    const DEFAULT_FLAG: u8 = 0x20;
+    const MAX_FLAG: u8 = 0x40;
+
+    fn flag(self) -> u8 {
+        match self {
+            Self::Fast => Self::FAST_FLAG,
+            Self::Default => Self::DEFAULT_FLAG,
+            Self::Max => Self::MAX_FLAG,
+        }
+    }
+}
+
+impl FromStr for CompressionLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(Self::Fast),
+            "default" => Ok(Self::Default),
+            "max" => Ok(Self::Max),
+            _ => bail!("Unknown compression level '{}' (expected fast, default or max)", s),
+        }
+    }
+}
+
+/// Split LSLib's `CompressionFlags` byte into its method (low nibble) and level (high nibble).
+pub fn parse_compression_flags(flags: u8) -> Result<(CompressionMethod, CompressionLevel)> {
+    let method = CompressionMethod::from_u32(u32::from(flags & 0x0F))
+        .ok_or_else(|| anyhow::anyhow!("Unsupported compression method {} in flags 0x{:02x}", flags & 0x0F, flags))?;
+
+    let level = match flags & 0xF0 {
+        CompressionLevel::FAST_FLAG => CompressionLevel::Fast,
+        CompressionLevel::MAX_FLAG => CompressionLevel::Max,
+        // Uncompressed files carry no level bits
+        0 | CompressionLevel::DEFAULT_FLAG => CompressionLevel::Default,
+        other => bail!("Unsupported compression level bits 0x{:02x} in flags 0x{:02x}", other, flags),
+    };
+
+    Ok((method, level))
+}
+
+/// Inverse of `parse_compression_flags`; uncompressed data has no level bits.
+pub fn make_compression_flags(method: CompressionMethod, level: CompressionLevel) -> u8 {
     match method {
-        CompressionMethod::None => {
-            Ok(data.to_vec())
-        },
-        CompressionMethod::Zlib => {
-            let mut decoder = ZlibDecoder::new(data);
-            let mut result = Vec::with_capacity(expected_size);
-            decoder.read_to_end(&mut result)?;
-            Ok(result)
-        },
+        CompressionMethod::None => 0,
+        _ => method as u8 | level.flag(),
+    }
+}
+
+/// Decompressed data that does not have the expected length, returned through `anyhow`
+/// so callers can downcast. Decompression stops one byte past `expected`, so a longer
+/// output is reported as `expected + 1` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.actual > self.expected {
+            write!(f, "decompressed to more than the expected {} bytes", self.expected)
+        } else {
+            write!(f, "decompressed to {} bytes, expected {}", self.actual, self.expected)
+        }
+    }
+}
+
+impl std::error::Error for SizeMismatch {}
+
+/// Largest buffer reserved up front; sizes come from file headers, so larger outputs grow as they are read.
+const MAX_INITIAL_CAPACITY: usize = 1 << 24;
+
+/// An LZ4 sequence expands to at most 255 bytes per input byte (plus the final literals).
+const MAX_LZ4_RATIO: usize = 255;
+
+/// Decompress a chunk to exactly `expected_size` bytes. `chunked` selects the LZ4 frame
+/// format, which LSF uses for every chunk but the strings since v2; otherwise LZ4 data
+/// is a raw block.
+pub fn decompress(data: &[u8], method: CompressionMethod, expected_size: usize, chunked: bool) -> Result<Vec<u8>> {
+    let result = match method {
+        CompressionMethod::None => data.to_vec(),
+        CompressionMethod::Zlib => read_bounded(ZlibDecoder::new(data), expected_size)?,
         CompressionMethod::Lz4 => {
-            // For LZ4, we need to handle frame format vs block format
-            if data.starts_with(&[0x04, 0x22, 0x4D, 0x18]) {
-                // LZ4 frame format
-                let mut decoder = lz4_flex::frame::FrameDecoder::new(data);
-                let mut result = Vec::new();
-                decoder.read_to_end(&mut result)
-                    .map_err(|e| anyhow::anyhow!("LZ4 frame decompression failed: {}", e))?;
-                Ok(result)
+            if chunked {
+                read_bounded(lz4_flex::frame::FrameDecoder::new(data), expected_size)
+                    .map_err(|e| anyhow::anyhow!("LZ4 frame decompression failed: {}", e))?
             } else {
-                // LZ4 block format - need to know the uncompressed size
+                // LZ4 block format - need to know the uncompressed size, which is allocated up front
+                if expected_size > data.len().saturating_mul(MAX_LZ4_RATIO).saturating_add(16) {
+                    bail!("LZ4 block of {} bytes cannot decompress to {} bytes", data.len(), expected_size);
+                }
                 lz4_flex::decompress(data, expected_size)
-                    .map_err(|e| anyhow::anyhow!("LZ4 block decompression failed: {}", e))
+                    .map_err(|e| anyhow::anyhow!("LZ4 block decompression failed: {}", e))?
             }
         },
         CompressionMethod::Zstd => {
-            zstd::decode_all(data)
-                .map_err(|e| anyhow::anyhow!("Zstd decompression failed: {}", e))
+            let decoder = zstd::stream::read::Decoder::new(data)?;
+            read_bounded(decoder, expected_size)
+                .map_err(|e| anyhow::anyhow!("Zstd decompression failed: {}", e))?
         },
+    };
+
+    if result.len() != expected_size {
+        return Err(SizeMismatch { expected: expected_size, actual: result.len() }.into());
     }
+    Ok(result)
 }
 
-pub fn compress(data: &[u8], method: CompressionMethod, level: i32) -> Result<Vec<u8>> {
+/// Read at most one byte more than `expected_size` from `decoder`, so an output longer
+/// than declared is detected without decompressing all of it.
+fn read_bounded<R: Read>(decoder: R, expected_size: usize) -> std::io::Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(expected_size.min(MAX_INITIAL_CAPACITY));
+    decoder.take((expected_size as u64).saturating_add(1)).read_to_end(&mut result)?;
+    Ok(result)
+}
+
+/// Compress a chunk; see `decompress` for the meaning of `chunked`.
+///
+/// lz4_flex has no high-compression mode, so every LZ4 level produces the same output.
+pub fn compress(data: &[u8], method: CompressionMethod, level: CompressionLevel, chunked: bool) -> Result<Vec<u8>> {
     match method {
         CompressionMethod::None => {
             Ok(data.to_vec())
@@ -64,22 +178,35 @@ pub fn compress(data: &[u8], method: CompressionMethod, level: i32) -> Result<Ve
             use flate2::write::ZlibEncoder;
             use flate2::Compression;
             use std::io::Write;
-            
-            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level as u32));
+
+            let compression = match level {
+                CompressionLevel::Fast => Compression::fast(),
+                CompressionLevel::Default => Compression::default(),
+                CompressionLevel::Max => Compression::best(),
+            };
+            let mut encoder = ZlibEncoder::new(Vec::new(), compression);
             encoder.write_all(data)?;
             Ok(encoder.finish()?)
         },
         CompressionMethod::Lz4 => {
-            // Use LZ4 frame format for consistency
-            let mut output = Vec::new();
-            let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut output);
-            std::io::Write::write_all(&mut encoder, data)?;
-            encoder.finish()?;
-            Ok(output)
+            if chunked {
+                let mut output = Vec::new();
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut output);
+                std::io::Write::write_all(&mut encoder, data)?;
+                encoder.finish()?;
+                Ok(output)
+            } else {
+                Ok(lz4_flex::compress(data))
+            }
         },
         CompressionMethod::Zstd => {
-            zstd::encode_all(data, level)
+            let zstd_level = match level {
+                CompressionLevel::Fast => 1,
+                CompressionLevel::Default => zstd::DEFAULT_COMPRESSION_LEVEL,
+                CompressionLevel::Max => 19,
+            };
+            zstd::encode_all(data, zstd_level)
                 .map_err(|e| anyhow::anyhow!("Zstd compression failed: {}", e))
         },
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,138 @@
+use anyhow::Result;
+use std::fmt;
+
+/// How readers react to data they cannot represent.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOptions {
+    /// Fail on the first anomaly instead of skipping the affected item.
+    pub strict: bool,
+}
+
+/// A recoverable problem found while reading a resource, with as much location as is known.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Chunk the problem was found in (`"attributes"`, `"values"`, ...)
+    pub chunk: Option<&'static str>,
+    /// Byte offset inside the decompressed chunk
+    pub offset: Option<u64>,
+    /// Index of the node entry being read
+    pub node_index: Option<usize>,
+    /// Line and column (1-based) of the element in a text document
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Region the element belongs to
+    pub region: Option<String>,
+    /// Ids of the enclosing nodes from the region root down, joined with `/`
+    pub node_path: Option<String>,
+    /// Name of the attribute being read
+    pub attribute: Option<String>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            chunk: None,
+            offset: None,
+            node_index: None,
+            line: None,
+            column: None,
+            region: None,
+            node_path: None,
+            attribute: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn in_chunk(mut self, chunk: &'static str, offset: Option<u64>) -> Self {
+        self.chunk = Some(chunk);
+        self.offset = offset;
+        self
+    }
+
+    pub fn at_node(mut self, node_index: usize) -> Self {
+        self.node_index = Some(node_index);
+        self
+    }
+
+    pub fn at_position(mut self, line: usize, column: usize) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    pub fn in_region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    pub fn at_node_path(mut self, node_path: impl Into<String>) -> Self {
+        self.node_path = Some(node_path.into());
+        self
+    }
+
+    pub fn at_attribute(mut self, attribute: impl Into<String>) -> Self {
+        self.attribute = Some(attribute.into());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "line {}, column {}: ", line, column)?;
+        }
+        if let Some(chunk) = self.chunk {
+            write!(f, "{} chunk", chunk)?;
+            if let Some(offset) = self.offset {
+                write!(f, " @ 0x{:x}", offset)?;
+            }
+            write!(f, ": ")?;
+        }
+        if let Some(node_index) = self.node_index {
+            write!(f, "node {}: ", node_index)?;
+        }
+        if let Some(region) = &self.region {
+            write!(f, "region '{}': ", region)?;
+        }
+        if let Some(node_path) = &self.node_path {
+            write!(f, "node '{}': ", node_path)?;
+        }
+        if let Some(attribute) = &self.attribute {
+            write!(f, "attribute '{}': ", attribute)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Strict readers return the offending diagnostic as their error.
+impl std::error::Error for Diagnostic {}
+
+/// Warnings collected while reading, returned next to the `Resource`.
+#[derive(Debug, Clone, Default)]
+pub struct ReadReport {
+    pub warnings: Vec<Diagnostic>,
+    strict: bool,
+}
+
+impl ReadReport {
+    pub fn new(options: &ReadOptions) -> Self {
+        Self {
+            warnings: Vec::new(),
+            strict: options.strict,
+        }
+    }
+
+    /// Record a skipped item, or fail with it in strict mode.
+    pub fn warn(&mut self, diagnostic: Diagnostic) -> Result<()> {
+        if self.strict {
+            return Err(diagnostic.into());
+        }
+        self.warnings.push(diagnostic);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
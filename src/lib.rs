@@ -2,6 +2,7 @@ pub mod resource;
 pub mod lsf;
 pub mod lsx;
 pub mod compression;
+pub mod diagnostics;
 
 #[cfg(test)]
 mod tests {
@@ -59,8 +60,8 @@ mod tests {
         for test_file in lsf_files {
             println!("\n=== Testing file: {} ===", test_file.display());
             
-            match lsf::read_lsf(&test_file) {
-                Ok(resource) => {
+            match lsf::read_lsf(&test_file, &diagnostics::ReadOptions::default()) {
+                Ok((resource, _)) => {
                     println!("Successfully read LSF file!");
                     println!("Metadata: {:?}", resource.metadata);
                     println!("Regions count: {}", resource.regions.len());
@@ -101,15 +102,10 @@ mod tests {
             }
             
             // Read LSF file
-            let resource = lsf::read_lsf(&test_file).expect("Failed to read LSF");
+            let (resource, _) = lsf::read_lsf(&test_file, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
             
             // Convert to LSX
-            let output_filename = format!("test_strings_{}.lsx", test_file.file_stem().unwrap().to_string_lossy());
-            let output_file = Path::new(&output_filename);
-            lsx::write_lsx(&resource, output_file).expect("Failed to write LSX");
-            
-            // Read the generated XML as a string
-            let xml_content = fs::read_to_string(output_file).expect("Failed to read LSX file");
+            let xml_content = String::from_utf8(lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX")).expect("LSX is not UTF-8");
             
             println!("Generated XML content (first 500 chars):\n{}", &xml_content[..xml_content.len().min(500)]);
             
@@ -122,7 +118,7 @@ mod tests {
                     resource.regions.keys().any(|k| k.contains(expected_string)) ||
                     resource.regions.values().any(|region| {
                         region.nodes.iter().any(|node| {
-                            node.name.as_ref().map_or(false, |name| name.contains(expected_string)) ||
+                            node.id.contains(expected_string) ||
                             node.attributes.keys().any(|attr| attr.contains(expected_string)) ||
                             node.attributes.values().any(|attr| {
                                 match &attr.value {
@@ -152,14 +148,31 @@ mod tests {
                 expected_strings.iter().take(5).collect::<Vec<_>>()
             );
             
-            // Clean up
-            fs::remove_file(output_file).ok();
-            
             println!("String preservation test passed for {}! Found {}/{} strings", 
                      test_file.display(), found_strings, expected_strings.len().min(5));
         }
     }
 
+    /// Every region, node and attribute name of `resource` as a path, in order.
+    fn structure_paths(resource: &resource::Resource) -> Vec<String> {
+        fn visit(node: &resource::Node, prefix: &str, paths: &mut Vec<String>) {
+            let path = format!("{}/{}", prefix, node.id);
+            paths.push(path.clone());
+            paths.extend(node.attributes.keys().map(|name| format!("{}@{}", path, name)));
+            for child in &node.children {
+                visit(child, &path, paths);
+            }
+        }
+
+        let mut paths = Vec::new();
+        for (region_name, region) in &resource.regions {
+            for node in &region.nodes {
+                visit(node, region_name, &mut paths);
+            }
+        }
+        paths
+    }
+
     #[test]
     fn test_round_trip_conversion_all_files() {
         let lsf_files = get_lsf_files();
@@ -167,49 +180,28 @@ mod tests {
         
         for test_file in lsf_files {
             println!("\n=== Testing round-trip conversion for: {} ===", test_file.display());
-            
-            // Step 1: Read original LSF
-            let original_resource = lsf::read_lsf(&test_file).expect("Failed to read original LSF");
-            
-            // Step 2: Convert to LSX
-            let lsx_filename = format!("test_roundtrip_{}.lsx", test_file.file_stem().unwrap().to_string_lossy());
-            let lsx_file = Path::new(&lsx_filename);
-            lsx::write_lsx(&original_resource, lsx_file).expect("Failed to write LSX");
-            
-            // Step 3: Read LSX back
-            let lsx_resource = lsx::read_lsx(lsx_file).expect("Failed to read LSX");
-            
-            // Step 4: Convert back to LSF
-            let roundtrip_lsf_filename = format!("test_roundtrip_{}.lsf", test_file.file_stem().unwrap().to_string_lossy());
-            let roundtrip_lsf_file = Path::new(&roundtrip_lsf_filename);
-            lsf::write_lsf(&lsx_resource, roundtrip_lsf_file).expect("Failed to write LSF");
-            
-            // Step 5: Compare original and round-trip LSF files
-            let original_bytes = fs::read(&test_file).expect("Failed to read original LSF");
-            let roundtrip_bytes = fs::read(roundtrip_lsf_file).expect("Failed to read round-trip LSF");
-            
-            // Check that the files are reasonably similar in size 
-            let size_ratio = roundtrip_bytes.len() as f64 / original_bytes.len() as f64;
-            assert!(
-                size_ratio > 0.3 && size_ratio < 3.0,
-                "Round-trip file size differs significantly for {}: original {} bytes, round-trip {} bytes (ratio: {:.2})",
-                test_file.display(),
-                original_bytes.len(),
-                roundtrip_bytes.len(),
-                size_ratio
-            );
-            
-            // Verify that we can read the round-trip file successfully
-            let roundtrip_resource = lsf::read_lsf(roundtrip_lsf_file).expect("Failed to read round-trip LSF");
-            
-            // Check that basic structure is preserved
-            assert!(!roundtrip_resource.regions.is_empty(), "Round-trip file should have at least one region for {}", test_file.display());
-            
-            // Clean up
-            fs::remove_file(lsx_file).ok();
-            fs::remove_file(roundtrip_lsf_file).ok();
-            
-            println!("Round-trip test passed for {}! Size ratio: {:.2}", test_file.display(), size_ratio);
+
+            // LSF -> LSF: rewritten with the recorded layout, the file must come back unchanged
+            let (original_resource, _) = lsf::read_lsf(&test_file, &diagnostics::ReadOptions::default()).expect("Failed to read original LSF");
+            let layout = original_resource.lsf_layout.as_ref().expect("LSF layout not recorded");
+            let round_trip = lsf::verify_roundtrip(&test_file)
+                .unwrap_or_else(|e| panic!("LSF round trip failed for {}: {:#}", test_file.display(), e));
+            if layout.compression == compression::CompressionMethod::None || layout.version == lsf::LsfVersion::Initial {
+                assert_eq!(round_trip, lsf::RoundTrip::Identical, "{} was not rewritten byte-identical", test_file.display());
+            } else {
+                // Compressed chunks only have to decompress identically: our compressor's output may differ from LSLib's
+                assert!(matches!(round_trip, lsf::RoundTrip::Identical | lsf::RoundTrip::SameChunks));
+            }
+
+            // LSF -> LSX -> LSF: the tree and its attribute names must survive
+            let lsx_bytes = lsx::to_vec(&original_resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+            let (lsx_resource, _) = lsx::from_bytes(&lsx_bytes, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+            let roundtrip_bytes = lsf::to_vec(&lsx_resource, &lsf::WriteOptions::for_resource(&original_resource)).expect("Failed to write LSF");
+            let (roundtrip_resource, _) = lsf::from_bytes(&roundtrip_bytes, &diagnostics::ReadOptions::default()).expect("Failed to read round-trip LSF");
+            assert_eq!(structure_paths(&roundtrip_resource), structure_paths(&original_resource),
+                "Round trip through LSX changed the structure of {}", test_file.display());
+
+            println!("Round-trip test passed for {} ({:?})", test_file.display(), round_trip);
         }
     }
     
@@ -223,12 +215,12 @@ mod tests {
         println!("=== DIAGNOSTIC ANALYSIS FOR profile8.lsf ===");
         
         // Extract strings using command
-        let expected_strings = extract_strings_from_lsf(&test_file);
+        let expected_strings = extract_strings_from_lsf(test_file);
         println!("Strings command found {} strings", expected_strings.len());
         
         // Try to read with detailed diagnostics
-        match lsf::read_lsf(&test_file) {
-            Ok(resource) => {
+        match lsf::read_lsf(test_file, &diagnostics::ReadOptions::default()) {
+            Ok((resource, _)) => {
                 println!("✓ Successfully parsed LSF file");
                 println!("  Regions found: {}", resource.regions.len());
                 
@@ -252,29 +244,21 @@ mod tests {
                 println!("  Total attributes: {}", total_attrs);
                 
                 // Compare with what we expected from file size
-                let file_size = std::fs::metadata(&test_file).unwrap().len();
+                let file_size = std::fs::metadata(test_file).unwrap().len();
                 println!("  Original file size: {} bytes", file_size);
                 println!("  Data utilization: {:.1}%", (total_attrs * 50) as f64 / file_size as f64 * 100.0);
                 
                 // Now test round-trip to see data loss
-                let lsx_file = Path::new("diagnostic_profile8.lsx");
-                lsx::write_lsx(&resource, lsx_file).expect("Failed to write LSX");
+                let lsx_bytes = lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+                let (lsx_resource, _) = lsx::from_bytes(&lsx_bytes, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+                let roundtrip_bytes = lsf::to_vec(&lsx_resource, &lsf::WriteOptions::default()).expect("Failed to write LSF");
                 
-                let lsx_resource = lsx::read_lsx(lsx_file).expect("Failed to read LSX");
-                let roundtrip_lsf_file = Path::new("diagnostic_profile8_roundtrip.lsf");
-                lsf::write_lsf(&lsx_resource, roundtrip_lsf_file).expect("Failed to write LSF");
-                
-                let original_size = std::fs::metadata(&test_file).unwrap().len();
-                let roundtrip_size = std::fs::metadata(roundtrip_lsf_file).unwrap().len();
-                let ratio = roundtrip_size as f64 / original_size as f64;
+                let original_size = std::fs::metadata(test_file).unwrap().len();
+                let ratio = roundtrip_bytes.len() as f64 / original_size as f64;
                 
                 println!("  Round-trip size ratio: {:.3}", ratio);
                 println!("  Data loss: {:.1}%", (1.0 - ratio) * 100.0);
                 
-                // Clean up
-                std::fs::remove_file(lsx_file).ok();
-                std::fs::remove_file(roundtrip_lsf_file).ok();
-                
             },
             Err(e) => {
                 println!("✗ Failed to parse LSF file: {}", e);
@@ -290,24 +274,1255 @@ mod tests {
         for test_file in lsf_files {
             println!("\n=== Testing LSF to LSX conversion for: {} ===", test_file.display());
             
-            let resource = lsf::read_lsf(&test_file).expect("Failed to read LSF");
+            let (resource, _) = lsf::read_lsf(&test_file, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
             
-            let output_filename = format!("test_output_{}.lsx", test_file.file_stem().unwrap().to_string_lossy());
-            let output_file = Path::new(&output_filename);
-            lsx::write_lsx(&resource, output_file).expect("Failed to write LSX");
+            let lsx_bytes = lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
             
-            // Verify the XML file was created and can be read back
-            assert!(output_file.exists());
-            
-            let resource2 = lsx::read_lsx(output_file).expect("Failed to read back LSX");
+            // Verify the XML can be read back
+            let (resource2, _) = lsx::from_bytes(&lsx_bytes, &diagnostics::ReadOptions::default()).expect("Failed to read back LSX");
             
             // Basic verification
             assert_eq!(resource.regions.len(), resource2.regions.len());
             
-            // Clean up
-            fs::remove_file(output_file).ok();
-            
             println!("LSF to LSX conversion test passed for {}!", test_file.display());
         }
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_lsx_node_key_round_trip() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let input = dir.path().join("keys.lsx");
+        fs::write(&input, r#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <region id="Templates">
+    <node id="Templates">
+      <children>
+        <node id="GameObjects" key="MapKey">
+          <attribute id="MapKey" type="FixedString" value="abc"/>
+        </node>
+      </children>
+    </node>
+  </region>
+</save>"#).expect("Failed to write LSX");
+
+        let (resource, _) = lsx::read_lsx(&input, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        let output = dir.path().join("keys_out.lsx");
+        lsx::write_lsx(&resource, &output, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+        let (reread, _) = lsx::read_lsx(&output, &diagnostics::ReadOptions::default()).expect("Failed to read back LSX");
+
+        let child = &reread.regions["Templates"].nodes[0].children[0];
+        assert_eq!(child.key.as_deref(), Some("MapKey"));
+        assert!(reread.regions["Templates"].nodes[0].key.is_none());
+    }
+
+    /// Build an LSF of the given `version` holding a single `Root` node with the given
+    /// `(name, type id, value)` attributes, compressing each chunk the way LSLib does.
+    /// Without adjacency the entries use the V2 layouts. The engine version is 3.6.2.51
+    /// before v5 (32-bit header) and 4.0.9.331 from v5 on (64-bit header).
+    fn build_lsf(version: u32, method: compression::CompressionMethod, level: compression::CompressionLevel, adjacency: bool, attributes: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let names: Vec<&str> = std::iter::once("Root").chain(attributes.iter().map(|a| a.0)).collect();
+        let strings = single_bucket_strings(&names);
+
+        let first_attribute: i32 = if attributes.is_empty() { -1 } else { 0 };
+        let links: &[i32] = if adjacency { &[-1, -1, first_attribute] } else { &[first_attribute, -1] };
+        let mut nodes = 0u32.to_le_bytes().to_vec();
+        for link in links {
+            nodes.extend(link.to_le_bytes());
+        }
+
+        let mut attrs = Vec::new();
+        let mut values = Vec::new();
+        for (index, (_, type_id, value)) in attributes.iter().enumerate() {
+            attrs.extend((index as u32 + 1).to_le_bytes());
+            attrs.extend((type_id | (value.len() as u32) << 6).to_le_bytes());
+            if adjacency {
+                let next = if index + 1 < attributes.len() { index as i32 + 1 } else { -1 };
+                attrs.extend(next.to_le_bytes());
+                attrs.extend((values.len() as u32).to_le_bytes());
+            } else {
+                attrs.extend(0u32.to_le_bytes());
+            }
+            values.extend(*value);
+        }
+
+        assemble_lsf(version, method, level, adjacency, [&strings, &nodes, &attrs, &values])
+    }
+
+    /// A strings chunk with a single bucket, so the handle of each name is its position in the chain.
+    fn single_bucket_strings(names: &[&str]) -> Vec<u8> {
+        let mut strings = 1u32.to_le_bytes().to_vec();
+        strings.extend((names.len() as u16).to_le_bytes());
+        for name in names {
+            strings.extend((name.len() as u16).to_le_bytes());
+            strings.extend(name.as_bytes());
+        }
+        strings
+    }
+
+    /// Write the header and metadata for the strings, nodes, attributes and values chunks
+    /// (and an empty keys chunk from v6 on), followed by the chunks themselves.
+    fn assemble_lsf(version: u32, method: compression::CompressionMethod, level: compression::CompressionLevel, adjacency: bool, [strings, nodes, attrs, values]: [&[u8]; 4]) -> Vec<u8> {
+        // Only v6+ metadata has the keys chunk, and v1 has no LZ4 frames
+        let chunked = version >= 2;
+        let mut chunks: Vec<(&[u8], bool)> = vec![(strings, false), (&[], chunked), (nodes, chunked), (attrs, chunked), (values, chunked)];
+        if version < 6 {
+            chunks.remove(1);
+        }
+        let mut sizes = Vec::new();
+        let mut body = Vec::new();
+        for (data, chunked) in chunks {
+            let packed = if data.is_empty() {
+                Vec::new()
+            } else {
+                compression::compress(data, method, level, chunked).expect("Failed to compress chunk")
+            };
+            sizes.extend((data.len() as u32).to_le_bytes());
+            sizes.extend((packed.len() as u32).to_le_bytes());
+            body.extend(packed);
+        }
+
+        let mut file = b"LSOF".to_vec();
+        file.extend(version.to_le_bytes());
+        if version >= 5 {
+            file.extend(((4u64 << 55) | (9 << 31) | 331).to_le_bytes());
+        } else {
+            file.extend(((3u32 << 28) | (6 << 24) | (2 << 16) | 51).to_le_bytes());
+        }
+        file.extend(sizes);
+        file.push(compression::make_compression_flags(method, level));
+        file.extend([0u8; 3]);
+        let format = if adjacency { lsf::LsfMetadataFormat::KeysAndAdjacency } else { lsf::LsfMetadataFormat::None };
+        file.extend((format as u32).to_le_bytes());
+        file.extend(body);
+        file
+    }
+
+    /// A v7 LSF with V3 entries for `(name, parent index, next sibling index)` nodes and no attributes.
+    fn build_tree_lsf(nodes: &[(&str, i32, i32)]) -> Vec<u8> {
+        let names: Vec<&str> = nodes.iter().map(|node| node.0).collect();
+        let mut entries = Vec::new();
+        for (index, (_, parent, sibling)) in nodes.iter().enumerate() {
+            for field in [index as i32, *parent, *sibling, -1] {
+                entries.extend(field.to_le_bytes());
+            }
+        }
+        let method = compression::CompressionMethod::None;
+        assemble_lsf(7, method, compression::CompressionLevel::Default, true, [&single_bucket_strings(&names), &entries, &[], &[]])
+    }
+
+    /// `build_lsf` for the latest version.
+    fn build_single_node_lsf(method: compression::CompressionMethod, level: compression::CompressionLevel, adjacency: bool, attributes: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        build_lsf(7, method, level, adjacency, attributes)
+    }
+
+    #[test]
+    fn test_lsf_rebuilds_node_tree() {
+        // Children of different parents are interleaved; each parent keeps its children in file order
+        let data = build_tree_lsf(&[("Root", -1, -1), ("A", 0, 2), ("B", 0, -1), ("A1", 1, 5), ("B1", 2, -1), ("A2", 1, -1)]);
+        let (resource, _) = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSF");
+        let root = &resource.regions["Root"].nodes[0];
+        let tree: Vec<(&str, Option<&str>, Vec<&str>)> = root.children.iter()
+            .map(|child| (child.id.as_str(), child.parent.as_deref(), child.children.iter().map(|c| c.id.as_str()).collect()))
+            .collect();
+        assert_eq!(tree, [("A", Some("Root"), vec!["A1", "A2"]), ("B", Some("Root"), vec!["B1"])]);
+        assert_eq!(root.children[0].children[1].parent.as_deref(), Some("A"));
+
+        // Parent after the child
+        let invalid = [
+            (build_tree_lsf(&[("Root", -1, -1), ("A", 2, -1), ("B", 0, -1)]), "invalid parent index 2"),
+            (build_tree_lsf(&[("Root", -1, -1), ("A", -5, -1)]), "invalid parent index -5"),
+        ];
+        for (data, message) in invalid {
+            let error = lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect_err("Invalid parent links must be rejected");
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+
+        // Sibling links are not needed for the tree, so bad ones are only fatal in strict mode
+        let invalid = [
+            // Sibling with another parent
+            (build_tree_lsf(&[("Root", -1, -1), ("A", 0, 2), ("A1", 1, -1)]), 1, 2),
+            (build_tree_lsf(&[("Root", -1, -1), ("A", 0, 7)]), 1, 7),
+        ];
+        for (data, node_index, sibling) in invalid {
+            let (resource, report) = lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
+            assert_eq!(resource.regions["Root"].nodes[0].children[0].id, "A");
+            assert_eq!(report.warnings.len(), 1);
+            let warning = &report.warnings[0];
+            assert_eq!(warning.message, format!("invalid next sibling index {}", sibling));
+            assert_eq!((warning.chunk, warning.offset, warning.node_index), (Some("nodes"), Some(node_index as u64 * 16), Some(node_index)));
+
+            let error = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect_err("Strict mode must reject invalid sibling links");
+            assert_eq!(error.downcast_ref::<diagnostics::Diagnostic>(), Some(warning));
+        }
+    }
+
+    #[test]
+    fn test_lsf_resolves_name_handles() {
+        // Bucket 0 holds "Pad", bucket 1 holds "Skip" then "Root"
+        let mut strings = 2u32.to_le_bytes().to_vec();
+        for chain in [&["Pad"][..], &["Skip", "Root"]] {
+            strings.extend((chain.len() as u16).to_le_bytes());
+            for name in chain {
+                strings.extend((name.len() as u16).to_le_bytes());
+                strings.extend(name.as_bytes());
+            }
+        }
+        let read = |handle: u32| {
+            let mut node = Vec::new();
+            for field in [handle as i32, -1, -1, -1] {
+                node.extend(field.to_le_bytes());
+            }
+            let method = compression::CompressionMethod::None;
+            let data = assemble_lsf(7, method, compression::CompressionLevel::Default, true, [&strings, &node, &[], &[]]);
+            lsf::from_bytes(&data, &diagnostics::ReadOptions::default())
+        };
+
+        let (resource, _) = read(1 << 16 | 1).expect("Failed to resolve bucket 1, chain index 1");
+        assert_eq!(resource.regions["Root"].nodes[0].id, "Root");
+
+        for handle in [1 << 16 | 2, 2 << 16] {
+            let error = read(handle).expect_err("Unresolved handle must be rejected");
+            assert!(format!("{:#}", error).contains(&format!("String handle 0x{:08x} does not resolve", handle)), "{:#}", error);
+        }
+    }
+
+    #[test]
+    fn test_lsf_reads_every_version_layout() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        let attributes: &[(&str, u32, &[u8])] = &[("Level", 4, &7i32.to_le_bytes()), ("Name", 22, b"Fane\0"), ("Flag", 19, &[1])];
+        for version in 1..=7u32 {
+            for adjacency in [false, true] {
+                // Adjacency entries only exist from v3 on
+                if adjacency && version < 3 {
+                    continue;
+                }
+                for (method, level) in [(CompressionMethod::None, CompressionLevel::Default), (CompressionMethod::Lz4, CompressionLevel::Fast)] {
+                    let data = build_lsf(version, method, level, adjacency, attributes);
+                    let (resource, report) = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true })
+                        .unwrap_or_else(|e| panic!("Failed to read v{} LSF (adjacency {}): {:#}", version, adjacency, e));
+                    assert!(report.is_empty());
+
+                    let expected = if version >= 5 { (4, 0, 9, 331) } else { (3, 6, 2, 51) };
+                    let metadata = &resource.metadata;
+                    assert_eq!((metadata.major_version, metadata.minor_version, metadata.revision, metadata.build_number), expected);
+
+                    let node = &resource.regions["Root"].nodes[0];
+                    let values: Vec<String> = node.attributes.iter().map(|(name, attr)| format!("{}={}", name, attr.value)).collect();
+                    assert_eq!(values, ["Level=7", "Name=Fane", "Flag=True"], "v{} adjacency {}", version, adjacency);
+                    assert_eq!(resource.lsf_layout.expect("LSF layout not recorded").version as u32, version);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_engine_version_bit_layouts() {
+        use lsf::LsfVersion;
+
+        let bg3 = resource::Metadata { major_version: 4, minor_version: 0, revision: 9, build_number: 331 };
+        // 7/8/16/31 bits from v5 on, 4/4/8/16 before
+        for (version, packed) in [(LsfVersion::Bg3ExtendedHeader, 0x0200_0004_8000_014b_u64), (LsfVersion::Bg3, 0x4009_014b)] {
+            assert_eq!(lsf::pack_engine_version(&bg3, version).unwrap(), packed);
+            assert_eq!(lsf::unpack_engine_version(packed, version), bg3);
+        }
+        let widest = resource::Metadata { major_version: 127, minor_version: 255, revision: 65535, build_number: 0x7fff_ffff };
+        assert_eq!(lsf::pack_engine_version(&widest, LsfVersion::Bg3Patch3).unwrap(), u64::MAX >> 2);
+
+        let too_wide = [
+            (LsfVersion::Bg3Patch3, resource::Metadata { major_version: 128, ..bg3.clone() }),
+            (LsfVersion::Bg3Patch3, resource::Metadata { build_number: 0x8000_0000, ..bg3.clone() }),
+            (LsfVersion::ExtendedNodes, resource::Metadata { major_version: 16, ..bg3.clone() }),
+            (LsfVersion::ExtendedNodes, resource::Metadata { revision: 256, ..bg3.clone() }),
+            (LsfVersion::ExtendedNodes, resource::Metadata { build_number: 0x1_0000, ..bg3.clone() }),
+        ];
+        for (version, metadata) in &too_wide {
+            let error = lsf::pack_engine_version(metadata, *version).expect_err("Overflowing component must be rejected");
+            assert!(error.to_string().contains("does not fit"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_lsf_structural_errors() {
+        use compression::{CompressionMethod, CompressionLevel};
+        use lsf::LsfError;
+
+        let attributes: &[(&str, u32, &[u8])] = &[("Level", 4, &7i32.to_le_bytes())];
+        let read_error = |data: &[u8]| -> LsfError {
+            let error = lsf::from_bytes(data, &diagnostics::ReadOptions::default()).expect_err("Malformed LSF must be rejected");
+            error.downcast_ref::<LsfError>().cloned().unwrap_or_else(|| panic!("Not an LsfError: {:#}", error))
+        };
+
+        // The values chunk (4 bytes) is the last one, so dropping a byte truncates it
+        let data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, attributes);
+        assert_eq!(read_error(&data[..data.len() - 1]),
+            LsfError::TruncatedChunk { chunk: "values", offset: data.len() as u64 - 4, expected: 4, available: 3 });
+
+        let mut trailing = data.clone();
+        trailing.extend([0xAA, 0xBB]);
+        assert_eq!(read_error(&trailing), LsfError::TrailingData { offset: data.len() as u64, length: 2 });
+
+        // v7 metadata follows the 16-byte header; the values sizes are its fifth pair
+        let mut mismatched = build_single_node_lsf(CompressionMethod::Lz4, CompressionLevel::Default, true, attributes);
+        mismatched[48..52].copy_from_slice(&5u32.to_le_bytes());
+        assert_eq!(read_error(&mismatched), LsfError::ChunkSizeMismatch { chunk: "values", expected: 5, actual: 4 });
+    }
+
+    #[test]
+    fn test_lsf_compression_methods_and_levels() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        let cases = [
+            (CompressionMethod::Lz4, CompressionLevel::Fast),
+            (CompressionMethod::Lz4, CompressionLevel::Max),
+            (CompressionMethod::Zlib, CompressionLevel::Default),
+            (CompressionMethod::Zstd, CompressionLevel::Max),
+        ];
+        for (method, level) in cases {
+            let data = build_single_node_lsf(method, level, true, &[]);
+            let (resource, _) = lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect("Failed to read compressed LSF");
+            assert!(resource.regions.contains_key("Root"));
+            let layout = resource.lsf_layout.expect("LSF layout not recorded");
+            assert_eq!(layout.compression, method);
+            assert_eq!(layout.compression_level, level);
+        }
+
+        // Strings are always a raw LZ4 block, while the other chunks are frames
+        let data = b"LSOF chunk payload LSOF chunk payload";
+        let block = compression::compress(data, CompressionMethod::Lz4, CompressionLevel::Default, false).unwrap();
+        let frame = compression::compress(data, CompressionMethod::Lz4, CompressionLevel::Default, true).unwrap();
+        assert_ne!(block, frame);
+        assert_eq!(compression::decompress(&block, CompressionMethod::Lz4, data.len(), false).unwrap(), data);
+        assert_eq!(compression::decompress(&frame, CompressionMethod::Lz4, data.len(), true).unwrap(), data);
+
+        // Declared sizes are only trusted as far as the data goes: no huge allocation up front,
+        // and output past the declared size is an error rather than kept
+        let zeros = vec![0u8; 1000];
+        for (method, chunked) in [(CompressionMethod::Zlib, false), (CompressionMethod::Lz4, true), (CompressionMethod::Zstd, false)] {
+            let packed = compression::compress(&zeros, method, CompressionLevel::Default, chunked).unwrap();
+            for (declared, actual) in [(10, 11), (1 << 40, 1000)] {
+                let error = compression::decompress(&packed, method, declared, chunked).expect_err("Size mismatch must be rejected");
+                assert_eq!(error.downcast_ref::<compression::SizeMismatch>(), Some(&compression::SizeMismatch { expected: declared, actual }), "{:?}", method);
+            }
+        }
+        let packed = compression::compress(&zeros, CompressionMethod::Lz4, CompressionLevel::Default, false).unwrap();
+        assert!(compression::decompress(&packed, CompressionMethod::Lz4, 1 << 40, false).is_err());
+        assert!(compression::parse_compression_flags(0x82).is_err());
+    }
+
+    #[test]
+    fn test_lsf_metadata_format_selects_entry_layout() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        for (adjacency, format) in [(true, lsf::LsfMetadataFormat::KeysAndAdjacency), (false, lsf::LsfMetadataFormat::None)] {
+            let data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, adjacency, &[("Level", 4, &7i32.to_le_bytes())]);
+            let (resource, _) = lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
+            assert_eq!(resource.regions["Root"].nodes.len(), 1);
+            assert_eq!(resource.regions["Root"].nodes[0].attributes["Level"].value.to_string(), "7");
+            assert_eq!(resource.lsf_layout.expect("LSF layout not recorded").metadata_format, format);
+        }
+    }
+
+    #[test]
+    fn test_lsf_translated_strings_follow_value_format() {
+        use compression::{CompressionMethod, CompressionLevel};
+        use resource::{AttributeValue, TranslatedString, TranslatedFSString, TranslatedFSStringArgument};
+
+        fn text(s: &str) -> Vec<u8> {
+            let mut bytes = (s.len() as i32 + 1).to_le_bytes().to_vec();
+            bytes.extend(s.as_bytes());
+            bytes.push(0);
+            bytes
+        }
+        // `value` is inline text for DOS2, a u16 version otherwise
+        fn fs_string(versioned: bool, value: &str, handle: &str, arguments: &[(&str, Vec<u8>, &str)]) -> Vec<u8> {
+            let mut bytes = if versioned { 1u16.to_le_bytes().to_vec() } else { text(value) };
+            bytes.extend(text(handle));
+            bytes.extend((arguments.len() as i32).to_le_bytes());
+            for (key, string, value) in arguments {
+                bytes.extend(text(key));
+                bytes.extend(string);
+                bytes.extend(text(value));
+            }
+            bytes
+        }
+        let expected_fs = |versioned: bool| {
+            let version = if versioned { 1 } else { 0 };
+            let value = |text: &str| (!versioned).then(|| text.to_string());
+            let inner = TranslatedFSString { version, value: value("Inner"), handle: "h3".into(), arguments: vec![] };
+            AttributeValue::TranslatedFSString(TranslatedFSString {
+                version, value: value("Fmt"), handle: "h2".into(),
+                arguments: vec![TranslatedFSStringArgument { key: "Key".into(), string: inner, value: "Val".into() }],
+            })
+        };
+        let read = |version: u32, engine: Option<u32>, ts_versioned: bool, fs_versioned: bool| {
+            let mut ts = if ts_versioned { 2u16.to_le_bytes().to_vec() } else { text("Hello") };
+            ts.extend(text("h1"));
+            let fs = fs_string(fs_versioned, "Fmt", "h2", &[("Key", fs_string(fs_versioned, "Inner", "h3", &[]), "Val")]);
+            let mut data = build_lsf(version, CompressionMethod::None, CompressionLevel::Default, true, &[("Title", 28, &ts), ("Tooltip", 33, &fs)]);
+            if let Some(engine) = engine {
+                data[8..12].copy_from_slice(&engine.to_le_bytes());
+            }
+            let (resource, report) = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true })
+                .unwrap_or_else(|e| panic!("Failed to read v{} LSF: {:#}", version, e));
+            assert!(report.is_empty());
+            let attributes = resource.regions["Root"].nodes[0].attributes.clone();
+            (attributes["Title"].value.clone(), attributes["Tooltip"].value.clone())
+        };
+
+        // DOS2: v3 with engine 3.6.2.51 stores both values inline
+        let dos2_title = AttributeValue::TranslatedString(TranslatedString { version: 0, value: Some("Hello".into()), handle: "h1".into() });
+        assert_eq!(read(3, None, false, false), (dos2_title, expected_fs(false)));
+
+        // DOS2:DE engine 4.0.0.26 versions TranslatedString while the LSF version still keeps FS strings inline
+        let versioned_title = AttributeValue::TranslatedString(TranslatedString { version: 2, value: None, handle: "h1".into() });
+        assert_eq!(read(3, Some((4 << 28) | 0x1a), true, false), (versioned_title.clone(), expected_fs(false)));
+
+        // BG3 (v4+) versions both
+        for version in [4, 7] {
+            assert_eq!(read(version, None, true, true), (versioned_title.clone(), expected_fs(true)));
+        }
+
+        // Arguments may nest MAX_FS_STRING_DEPTH levels below the attribute's own string, and no further
+        let nested = |depth: usize| (0..depth).fold(fs_string(true, "", "leaf", &[]), |string, _| fs_string(true, "", "h", &[("Arg", string, "")]));
+        let read_nested = |depth: usize, strict: bool| {
+            let data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, &[("Tooltip", 33, &nested(depth))]);
+            lsf::from_bytes(&data, &diagnostics::ReadOptions { strict })
+        };
+        let (resource, _) = read_nested(lsf::MAX_FS_STRING_DEPTH, true).expect("Failed to read nested arguments within the limit");
+        assert!(resource.regions["Root"].nodes[0].attributes.contains_key("Tooltip"));
+
+        let (resource, report) = read_nested(lsf::MAX_FS_STRING_DEPTH + 1, false).expect("Failed to read LSF");
+        assert!(!resource.regions["Root"].nodes[0].attributes.contains_key("Tooltip"));
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].message.contains("nested deeper than 64"), "{}", report.warnings[0].message);
+        read_nested(lsf::MAX_FS_STRING_DEPTH + 1, true).expect_err("Strict mode must reject over-deep arguments");
+    }
+
+    #[test]
+    fn test_lsf_read_report_locates_skipped_attributes() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        let attributes: &[(&str, u32, &[u8])] = &[("Level", 4, &7i32.to_le_bytes()), ("Mystery", 63, &[1, 2]), ("Flag", 19, &[1])];
+        let data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, attributes);
+
+        let (resource, report) = lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
+        let attributes = &resource.regions["Root"].nodes[0].attributes;
+        assert!(attributes.contains_key("Level") && attributes.contains_key("Flag"));
+        assert!(!attributes.contains_key("Mystery"));
+
+        assert_eq!(report.warnings.len(), 1);
+        let warning = &report.warnings[0];
+        assert_eq!(warning.chunk, Some("values"));
+        assert_eq!(warning.offset, Some(4));
+        assert_eq!(warning.node_index, Some(0));
+        assert_eq!(warning.attribute.as_deref(), Some("Mystery"));
+
+        let error = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true })
+            .expect_err("Strict mode must reject unknown attribute types");
+        let diagnostic = error.downcast_ref::<diagnostics::Diagnostic>().expect("Strict error is not a Diagnostic");
+        assert_eq!(diagnostic, warning);
+    }
+
+    #[test]
+    fn test_lsf_rejects_value_length_mismatches() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        // A 2-byte int32, and a TranslatedString (u16 version, handle "h") with a stray trailing byte
+        let title: &[u8] = &[1, 0, 2, 0, 0, 0, b'h', 0, 0xAA];
+        let attributes: &[(&str, u32, &[u8])] = &[("Short", 4, &[7, 0]), ("Title", 28, title), ("Next", 4, &9i32.to_le_bytes())];
+        let data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, attributes);
+
+        let (resource, report) = lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
+        let names: Vec<&String> = resource.regions["Root"].nodes[0].attributes.keys().collect();
+        assert_eq!(names, ["Next"]);
+        let located: Vec<(Option<&str>, Option<u64>)> = report.warnings.iter().map(|w| (w.attribute.as_deref(), w.offset)).collect();
+        assert_eq!(located, [(Some("Short"), Some(0)), (Some("Title"), Some(2))]);
+        assert!(report.warnings[0].message.contains("Int, which is 4 bytes"), "{}", report.warnings[0].message);
+        assert!(report.warnings[1].message.contains("encoding is 8 bytes"), "{}", report.warnings[1].message);
+
+        lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect_err("Strict mode must reject length mismatches");
+    }
+
+    #[test]
+    fn test_lsf_read_report_locates_broken_attribute_chains() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        let strings = single_bucket_strings(&["Root", "A", "B"]);
+        let int = 4 | 4 << 6;
+        let read = |adjacency: bool, nodes: &[i32], attrs: &[[i32; 4]]| {
+            let nodes: Vec<u8> = nodes.iter().flat_map(|field| field.to_le_bytes()).collect();
+            let entry_fields = if adjacency { 4 } else { 3 };
+            let attrs: Vec<u8> = attrs.iter().flat_map(|entry| entry[..entry_fields].iter().flat_map(|field| field.to_le_bytes())).collect();
+            let values: Vec<u8> = [1i32, 2].iter().flat_map(|value| value.to_le_bytes()).collect();
+            let data = assemble_lsf(7, CompressionMethod::None, CompressionLevel::Default, adjacency, [&strings, &nodes, &attrs, &values]);
+            lsf::from_bytes(&data, &diagnostics::ReadOptions::default()).expect("Failed to read LSF")
+        };
+
+        // V3 entries are 16 bytes: a chain that links B back to itself, and one that leaves the chunk
+        let cases = [
+            (true, vec![0, -1, -1, 0], vec![[1, int, 1, 0], [2, int, 1, 4]], "circular attribute chain at attribute index 1", 16),
+            (true, vec![0, -1, -1, 0], vec![[1, int, 1, 0], [2, int, 5, 4]], "attribute index 5 out of bounds (count: 2)", 80),
+            // V2 entries are 12 bytes
+            (false, vec![0, 3, -1], vec![[1, int, 0, 0]], "attribute index 3 out of bounds (count: 1)", 36),
+        ];
+        for (adjacency, nodes, attrs, message, offset) in cases {
+            let (_, report) = read(adjacency, &nodes, &attrs);
+            assert_eq!(report.warnings.len(), 1, "{}", message);
+            let warning = &report.warnings[0];
+            assert_eq!(warning.message, message);
+            assert_eq!(warning.chunk, Some("attributes"));
+            assert_eq!(warning.offset, Some(offset), "{}", message);
+            assert_eq!(warning.node_index, Some(0));
+        }
+    }
+
+    #[test]
+    fn test_guids_use_lslib_byte_order() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        // .NET Guid fields are little-endian and ByteSwapGuids swaps the last 8 bytes in pairs
+        let stored: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        let text = "33221100-5544-7766-9988-bbaaddccffee";
+        let data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, &[("Id", 31, &stored)]);
+        let (resource, _) = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSF");
+        assert_eq!(resource.regions["Root"].nodes[0].attributes["Id"].value.to_string(), text);
+
+        let written = lsf::to_vec(&resource, &lsf::WriteOptions::for_resource(&resource)).expect("Failed to write LSF");
+        assert_eq!(written, data);
+
+        // LSX without bswap_guids in lslib_meta holds the unswapped text
+        let lsx_text = format!(r#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331" lslib_meta="v1"/>
+  <region id="Root">
+    <node id="Root">
+      <attribute id="Id" type="guid" value="{}"/>
+    </node>
+  </region>
+</save>"#, "33221100-5544-7766-8899-aabbccddeeff");
+        let (from_lsx, _) = lsx::from_bytes(lsx_text.as_bytes(), &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSX");
+        assert_eq!(from_lsx.regions["Root"].nodes[0].attributes["Id"].value.to_string(), text);
+        let options = lsx::LsxWriteOptions { lslib_meta: lsx::LslibMeta::Value("v1".to_string()), ..lsx::LsxWriteOptions::default() };
+        let rewritten = String::from_utf8(lsx::to_vec(&from_lsx, &options).unwrap()).unwrap();
+        assert!(rewritten.contains(r#"value="33221100-5544-7766-8899-aabbccddeeff""#));
+        let swapped = String::from_utf8(lsx::to_vec(&from_lsx, &lsx::LsxWriteOptions::lslib()).unwrap()).unwrap();
+        assert!(swapped.contains(&format!(r#"value="{}""#, text)));
+    }
+
+    #[test]
+    fn test_lsx_strict_mode_rejects_unknown_attribute_types() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <region id="Config">
+    <node id="Config">
+      <attribute id="Known" type="int32" value="1"/>
+      <attribute id="Odd" type="quaternion" value="0 0 0 1"/>
+    </node>
+  </region>
+</save>"#;
+
+        let (resource, report) = lsx::from_bytes(input, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        assert!(resource.regions["Config"].nodes[0].attributes.contains_key("Known"));
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].attribute.as_deref(), Some("Odd"));
+
+        assert!(lsx::from_bytes(input, &diagnostics::ReadOptions { strict: true }).is_err());
+    }
+
+    #[test]
+    fn test_lsx_reports_orphan_nodes_and_duplicate_regions() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <node id="Stray">
+    <attribute id="Lost" type="int32" value="1"/>
+  </node>
+  <region id="Config">
+    <node id="Config">
+      <attribute id="First" type="int32" value="1"/>
+    </node>
+  </region>
+  <region id="Config">
+    <node id="Config">
+      <attribute id="Second" type="int32" value="2"/>
+    </node>
+  </region>
+</save>"#;
+
+        let (resource, report) = lsx::from_bytes(input, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        assert_eq!(resource.regions.len(), 1);
+        assert!(resource.regions["Config"].nodes[0].attributes.contains_key("First"));
+
+        assert_eq!(report.warnings.len(), 2);
+        assert_eq!(report.warnings[0].message, "node outside of a region");
+        assert_eq!(report.warnings[0].node_path.as_deref(), Some("Stray"));
+        assert_eq!(report.warnings[1].message, "duplicate region 'Config'");
+        assert_eq!(report.warnings[1].line, Some(16));
+
+        let error = lsx::from_bytes(input, &diagnostics::ReadOptions { strict: true })
+            .expect_err("Strict mode must reject nodes outside of a region");
+        let diagnostic = error.downcast_ref::<diagnostics::Diagnostic>().expect("Strict error is not a Diagnostic");
+        assert_eq!(diagnostic, &report.warnings[0]);
+
+        // A region opened before the previous one closed keeps the nodes read so far
+        let nested = br#"<save>
+  <region id="Outer">
+    <node id="Outer"/>
+    <region id="Inner">
+      <node id="Inner"/>
+    </region>
+  </region>
+</save>"#;
+        let (resource, report) = lsx::from_bytes(nested, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        let regions: Vec<(&String, usize)> = resource.regions.iter().map(|(name, region)| (name, region.nodes.len())).collect();
+        assert_eq!(regions, [(&"Outer".to_string(), 1), (&"Inner".to_string(), 1)]);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(report.warnings[0].message, "region 'Inner' opened inside region 'Outer'");
+        assert_eq!(report.warnings[0].line, Some(4));
+        lsx::from_bytes(nested, &diagnostics::ReadOptions { strict: true }).expect_err("Strict mode must reject nested regions");
+    }
+
+    #[test]
+    fn test_lsx_diagnostics_carry_position_and_path() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <region id="Templates">
+    <node id="Templates">
+      <children>
+        <node id="GameObjects">
+          <attribute id="Level" type="int32" value="3"/>
+          <attribute id="Odd" type="quaternion" value="0 0 0 1"/>
+        </node>
+      </children>
+    </node>
+  </region>
+</save>"#;
+
+        let (resource, report) = lsx::from_bytes(input, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        let attributes = &resource.regions["Templates"].nodes[0].children[0].attributes;
+        assert!(attributes.contains_key("Level") && !attributes.contains_key("Odd"));
+
+        assert_eq!(report.warnings.len(), 1);
+        let unknown = &report.warnings[0];
+        assert_eq!((unknown.line, unknown.column), (Some(9), Some(11)));
+        assert_eq!(unknown.region.as_deref(), Some("Templates"));
+        assert_eq!(unknown.node_path.as_deref(), Some("Templates/GameObjects"));
+        assert_eq!(unknown.attribute.as_deref(), Some("Odd"));
+
+        // A value that does not parse fails the read in both modes
+        let malformed = [
+            (r#"id="Position" type="fvec3" value="1 2""#, "attribute 'Position': invalid fvec3 value: Vec3 requires 3 values"),
+            (r#"id="Visible" type="bool" value="yes""#, "attribute 'Visible': invalid bool value: invalid bool 'yes'"),
+        ];
+        for (replacement, message) in malformed {
+            let malformed = String::from_utf8_lossy(input).replace(r#"id="Level" type="int32" value="3""#, replacement);
+            for strict in [false, true] {
+                let error = lsx::from_bytes(malformed.as_bytes(), &diagnostics::ReadOptions { strict })
+                    .expect_err("Malformed values must be rejected");
+                let diagnostic = error.downcast_ref::<diagnostics::Diagnostic>().expect("Value error is not a Diagnostic");
+                assert_eq!(diagnostic.to_string(),
+                    format!("line 8, column 11: region 'Templates': node 'Templates/GameObjects': {}", message));
+            }
+        }
+
+        let truncated = b"<save>\n  <region id=\"Config\">\n    <node id=\"Config\">\n    </region>\n</save>";
+        let error = lsx::from_bytes(truncated, &diagnostics::ReadOptions::default()).expect_err("Mismatched tags must fail");
+        let diagnostic = error.downcast_ref::<diagnostics::Diagnostic>().expect("XML error is not a Diagnostic");
+        assert_eq!(diagnostic.line, Some(4));
+        assert_eq!(diagnostic.node_path.as_deref(), Some("Config"));
+    }
+
+    #[test]
+    fn test_lsx_lslib_layout() {
+        use resource::{AttributeType, AttributeValue};
+
+        let mut regions = indexmap::IndexMap::new();
+        regions.insert("Config".to_string(), resource::Region {
+            name: "Config".to_string(),
+            nodes: vec![test_node("Config", vec![
+                ("Scale", AttributeType::Float, AttributeValue::Float(0.00001)),
+                ("Offset", AttributeType::Vec3, AttributeValue::Vec3([1e9, -0.5, 0.0001])),
+                ("Weight", AttributeType::Double, AttributeValue::Double(1e15)),
+                ("Label", AttributeType::FixedString, AttributeValue::FixedString("a \"b\" & <c>\n'd'".to_string())),
+            ], vec![test_node("Empty", vec![], vec![])])],
+        });
+        let resource = resource::Resource {
+            metadata: resource::Metadata { major_version: 4, minor_version: 0, revision: 9, build_number: 331 },
+            regions,
+            lsf_layout: None,
+        };
+
+        let data = lsx::to_vec(&resource, &lsx::LsxWriteOptions::lslib()).expect("Failed to write LSX");
+        let expected = [
+            "\u{feff}<?xml version=\"1.0\" encoding=\"utf-8\"?>",
+            "<save>",
+            "\t<version major=\"4\" minor=\"0\" revision=\"9\" build=\"331\" lslib_meta=\"v1,bswap_guids,lsf_keys_adjacency\" />",
+            "\t<region id=\"Config\">",
+            "\t\t<node id=\"Config\">",
+            "\t\t\t<attribute id=\"Scale\" type=\"float\" value=\"1E-05\" />",
+            "\t\t\t<attribute id=\"Offset\" type=\"fvec3\" value=\"1E+09 -0.5 0.0001\" />",
+            "\t\t\t<attribute id=\"Weight\" type=\"double\" value=\"1E+15\" />",
+            "\t\t\t<attribute id=\"Label\" type=\"FixedString\" value=\"a &quot;b&quot; &amp; &lt;c&gt;&#xA;'d'\" />",
+            "\t\t\t<children>",
+            "\t\t\t\t<node id=\"Empty\" />",
+            "\t\t\t</children>",
+            "\t\t</node>",
+            "\t</region>",
+            "</save>",
+        ].join("\r\n");
+        assert_eq!(String::from_utf8(data.clone()).unwrap(), expected);
+
+        let (read_back, _) = lsx::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSLib layout");
+        let config = &read_back.regions["Config"].nodes[0];
+        assert_eq!(config.attributes, resource.regions["Config"].nodes[0].attributes);
+        assert_eq!(config.children.len(), 1);
+        assert_eq!(config.children[0].id, "Empty");
+
+        let options = lsx::LsxWriteOptions {
+            indent: lsx::Indent::Spaces(4),
+            empty_children: lsx::EmptyChildren::SelfClosing,
+            lslib_meta: lsx::LslibMeta::Value("v1".to_string()),
+            ..lsx::LsxWriteOptions::default()
+        };
+        let text = String::from_utf8(lsx::to_vec(&resource, &options).expect("Failed to write LSX")).unwrap();
+        assert!(text.starts_with("<?xml"));
+        assert!(text.contains("\n    <version major=\"4\" minor=\"0\" revision=\"9\" build=\"331\" lslib_meta=\"v1\"/>"));
+        assert!(text.contains("value=\"0.00001\""));
+
+        // .NET switches doubles to exponent notation beyond 15 integer digits, or beyond the
+        // number of significant digits when that is larger
+        let doubles = [
+            (1e14, "100000000000000"), (1e15, "1E+15"), (1e16, "1E+16"),
+            (123456789012345680.0, "1.2345678901234568E+17"), (1234567890123456.7, "1234567890123456.8"),
+        ];
+        for (value, text) in doubles {
+            let node = test_node("Config", vec![("Weight", AttributeType::Double, AttributeValue::Double(value))], vec![]);
+            let mut regions = indexmap::IndexMap::new();
+            regions.insert("Config".to_string(), resource::Region { name: "Config".to_string(), nodes: vec![node] });
+            let resource = resource::Resource { regions, ..resource.clone() };
+            let written = String::from_utf8(lsx::to_vec(&resource, &lsx::LsxWriteOptions::lslib()).unwrap()).unwrap();
+            assert!(written.contains(&format!("type=\"double\" value=\"{}\"", text)), "{} was not written as {}", value, text);
+        }
+        assert!(text.contains("<node id=\"Empty\">\n                    <children/>\n                </node>"));
+        assert!(!text.contains('\r'));
+    }
+
+    #[test]
+    fn test_lsx_type_dialects() {
+        use resource::{AttributeType, AttributeValue};
+
+        let dos2 = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="3" minor="6" revision="2" build="51"/>
+  <region id="Config">
+    <node id="Config">
+      <attribute id="Name" type="22" value="Fane"/>
+      <attribute id="Level" type="4" value="7"/>
+      <attribute id="Rotation" type="mat3" value="1 0 0 0 1 0 0 0 1"/>
+    </node>
+  </region>
+</save>"#;
+        let (resource, report) = lsx::from_bytes(dos2, &diagnostics::ReadOptions { strict: true }).expect("Failed to read DOS2 LSX");
+        assert!(report.is_empty());
+        let attributes = &resource.regions["Config"].nodes[0].attributes;
+        assert_eq!(attributes["Name"].value, AttributeValue::FixedString("Fane".to_string()));
+        assert_eq!(attributes["Level"].value, AttributeValue::Int(7));
+        assert_eq!(attributes["Rotation"].attribute_type, AttributeType::Mat3);
+
+        // Only the canonical spelling of an id is a type
+        assert_eq!(AttributeType::from_str("0"), Some(AttributeType::None));
+        for spelling in ["+22", "022", " 22", "-0", "256"] {
+            assert_eq!(AttributeType::from_str(spelling), None, "{:?}", spelling);
+        }
+        let signed = String::from_utf8_lossy(dos2).replace(r#"type="22""#, r#"type="+22""#);
+        lsx::from_bytes(signed.as_bytes(), &diagnostics::ReadOptions { strict: true }).expect_err("Signed type id must be rejected");
+
+        // Written back in the dialect of its version, or as asked
+        let text = String::from_utf8(lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).unwrap()).unwrap();
+        assert!(text.contains(r#"<attribute id="Name" type="22" value="Fane"/>"#));
+        assert!(text.contains(r#"<attribute id="Rotation" type="15" "#));
+        let bg3 = lsx::LsxWriteOptions { dialect: Some(lsx::LsxDialect::Bg3), ..lsx::LsxWriteOptions::default() };
+        let text = String::from_utf8(lsx::to_vec(&resource, &bg3).unwrap()).unwrap();
+        assert!(text.contains(r#"<attribute id="Name" type="FixedString" value="Fane"/>"#));
+        assert!(text.contains(r#"<attribute id="Rotation" type="mat3x3" "#));
+
+        // Without a version the document is taken to be BG3's
+        let unversioned = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <region id="Config">
+    <node id="Config">
+      <attribute id="Name" type="FixedString" value="Fane"/>
+    </node>
+  </region>
+</save>"#;
+        let (resource, _) = lsx::from_bytes(unversioned, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSX");
+        assert_eq!(lsx::LsxDialect::for_metadata(&resource.metadata), lsx::LsxDialect::Bg3);
+        let text = String::from_utf8(lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).unwrap()).unwrap();
+        assert!(text.contains(r#"<version major="4" "#));
+        assert!(text.contains(r#"<attribute id="Name" type="FixedString" value="Fane"/>"#));
+
+        // Types whose names used to collide keep their identity through LSX
+        let node = test_node("Config", vec![
+            ("Plain", AttributeType::String, AttributeValue::String("a".to_string())),
+            ("Localized", AttributeType::LSString, AttributeValue::LSString("b".to_string())),
+            ("Old", AttributeType::LongLong, AttributeValue::LongLong(-1)),
+            ("New", AttributeType::Int64, AttributeValue::Int64(-2)),
+            ("Pair", AttributeType::Mat2, AttributeValue::Mat2([1.0, 0.0, 0.0, 1.0])),
+        ], vec![]);
+        let mut regions = indexmap::IndexMap::new();
+        regions.insert("Config".to_string(), resource::Region { name: "Config".to_string(), nodes: vec![node] });
+        for major_version in [3, 4] {
+            let original = resource::Resource {
+                metadata: resource::Metadata { major_version, minor_version: 0, revision: 0, build_number: 0 },
+                regions: regions.clone(),
+                lsf_layout: None,
+            };
+            let data = lsx::to_vec(&original, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+            let (read_back, _) = lsx::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSX");
+            assert_eq!(read_back.regions["Config"].nodes[0].attributes, original.regions["Config"].nodes[0].attributes);
+        }
+    }
+
+    fn test_node(name: &str, attributes: Vec<(&str, resource::AttributeType, resource::AttributeValue)>, children: Vec<resource::Node>) -> resource::Node {
+        resource::Node {
+            id: name.to_string(),
+            parent: None,
+            key: None,
+            attributes: attributes.into_iter()
+                .map(|(id, attribute_type, value)| (id.to_string(), resource::NodeAttribute { attribute_type, value }))
+                .collect(),
+            children,
+        }
+    }
+
+    /// Two regions: `Config` with a keyed child and a grandchild, and an empty `Other`.
+    fn test_tree_resource() -> resource::Resource {
+        use resource::{AttributeType, AttributeValue};
+
+        let mut keyed = test_node("Child", vec![("Name", AttributeType::FixedString, AttributeValue::FixedString("a".into())), ("Pos", AttributeType::Vec3, AttributeValue::Vec3([1.0, 2.5, 3.0]))], vec![]);
+        keyed.key = Some("Name".into());
+        let config = test_node("Config", vec![("Version", AttributeType::Int, AttributeValue::Int(42)), ("Title", AttributeType::LSString, AttributeValue::LSString("hello".into()))], vec![
+            keyed,
+            test_node("Child", vec![("Name", AttributeType::FixedString, AttributeValue::FixedString("b".into()))], vec![
+                test_node("Grand", vec![("Flag", AttributeType::Bool, AttributeValue::Bool(true))], vec![]),
+            ]),
+        ]);
+        let mut resource = resource::Resource {
+            metadata: resource::Metadata { major_version: 4, minor_version: 7, revision: 1, build_number: 613 },
+            regions: indexmap::IndexMap::new(),
+            lsf_layout: None,
+        };
+        for root in [config, test_node("Other", vec![], vec![])] {
+            let name = root.id.clone();
+            resource.regions.insert(name.clone(), resource::Region { name, nodes: vec![root] });
+        }
+        resource
+    }
+
+    #[test]
+    fn test_lsf_writer_round_trips_node_tree() {
+        let resource = test_tree_resource();
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let path = dir.path().join("tree.lsf");
+        lsf::write_lsf(&resource, &path, &lsf::WriteOptions::default()).expect("Failed to write LSF");
+        let (reread, report) = lsf::read_lsf(&path, &diagnostics::ReadOptions { strict: true }).expect("Failed to read written LSF");
+        assert!(report.is_empty());
+        assert!(reread.regions["Other"].nodes[0].children.is_empty());
+
+        let config = &reread.regions["Config"].nodes[0];
+        assert_eq!(config.attributes["Version"].value.to_string(), "42");
+        assert_eq!(config.attributes["Title"].value.to_string(), "hello");
+        assert_eq!(config.children.len(), 2);
+        assert_eq!(config.children[0].key.as_deref(), Some("Name"));
+        assert_eq!(config.children[0].attributes["Name"].value.to_string(), "a");
+        assert_eq!(config.children[0].attributes["Pos"].value.to_string(), "1 2.5 3");
+        assert_eq!(config.children[1].attributes["Name"].value.to_string(), "b");
+        let grand = &config.children[1].children[0];
+        assert_eq!(grand.id, "Grand");
+        assert_eq!(grand.attributes["Flag"].value.to_string(), "True");
+    }
+
+    #[test]
+    fn test_lsf_writer_compression_options() {
+        use compression::{CompressionMethod, CompressionLevel};
+
+        let resource = test_tree_resource();
+        let cases = [
+            (CompressionMethod::None, CompressionLevel::Default),
+            (CompressionMethod::Zlib, CompressionLevel::Fast),
+            (CompressionMethod::Lz4, CompressionLevel::Default),
+            (CompressionMethod::Zstd, CompressionLevel::Max),
+        ];
+        for (compression, level) in cases {
+            let data = lsf::to_vec(&resource, &lsf::WriteOptions { compression, level, ..Default::default() }).expect("Failed to write LSF");
+            let (reread, _) = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read written LSF");
+            let layout = reread.lsf_layout.expect("LSF layout not recorded");
+            assert_eq!((layout.compression, layout.compression_level), (compression, level));
+            let grand = &reread.regions["Config"].nodes[0].children[1].children[0];
+            assert_eq!(grand.attributes["Flag"].value.to_string(), "True");
+        }
+    }
+
+    #[test]
+    fn test_lsf_string_table_uses_lslib_buckets() {
+        let mut resource = resource::Resource {
+            metadata: resource::Metadata { major_version: 4, minor_version: 0, revision: 0, build_number: 0 },
+            regions: indexmap::IndexMap::new(),
+            lsf_layout: None,
+        };
+        resource.regions.insert("Config".into(), resource::Region {
+            name: "Config".into(),
+            nodes: vec![test_node("Config", vec![("Version", resource::AttributeType::Int, resource::AttributeValue::Int(1))], vec![])],
+        });
+
+        let options = lsf::WriteOptions { compression: compression::CompressionMethod::None, ..Default::default() };
+        let data = lsf::to_vec(&resource, &options).expect("Failed to write LSF");
+
+        // The strings chunk follows the 16-byte header and 48-byte metadata
+        let mut pos = 64;
+        let read_u16 = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        assert_eq!(u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()), 0x200);
+        pos += 4;
+        let mut bucket_of = indexmap::IndexMap::new();
+        for bucket in 0..0x200 {
+            let chain_length = read_u16(pos);
+            pos += 2;
+            for _ in 0..chain_length {
+                let length = read_u16(pos);
+                bucket_of.insert(String::from_utf8(data[pos + 2..pos + 2 + length].to_vec()).unwrap(), bucket);
+                pos += 2 + length;
+            }
+        }
+        assert_eq!(bucket_of["Config"], 362);
+        assert_eq!(bucket_of["Version"], 504);
+    }
+
+    #[test]
+    fn test_lsf_writer_targets_every_version() {
+        for number in 1..=7 {
+            let version = lsf::LsfVersion::from_u32(number).unwrap();
+            let mut resource = test_tree_resource();
+            let options = lsf::WriteOptions { version, ..Default::default() };
+
+            if version < lsf::LsfVersion::Bg3NodeKeys {
+                // Node keys only exist since v6
+                assert!(lsf::to_vec(&resource, &options).is_err());
+                resource.regions.get_mut("Config").unwrap().nodes[0].children[0].key = None;
+            }
+            let data = lsf::to_vec(&resource, &options).expect("Failed to write LSF");
+
+            let (reread, _) = lsf::from_bytes(&data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read written LSF");
+            assert_eq!(reread.lsf_layout.expect("LSF layout not recorded").version, version);
+            assert_eq!(reread.metadata.build_number, 613);
+            let config = &reread.regions["Config"].nodes[0];
+            assert_eq!(config.attributes["Version"].value.to_string(), "42");
+            assert_eq!(config.children[0].attributes["Pos"].value.to_string(), "1 2.5 3");
+            assert_eq!(config.children[1].children[0].attributes["Flag"].value.to_string(), "True");
+        }
+    }
+
+    /// Write `value` as the only attribute of a single-node LSF, read it back and write it
+    /// again: the value must survive and both files must be identical.
+    fn assert_value_round_trip(version: lsf::LsfVersion, attribute_type: resource::AttributeType, value: resource::AttributeValue) {
+        // The engine version also decides how translated strings are stored, so pair DOS2 files with a DOS2 engine
+        let metadata = if version >= lsf::LsfVersion::Bg3 {
+            resource::Metadata { major_version: 4, minor_version: 0, revision: 9, build_number: 331 }
+        } else {
+            resource::Metadata { major_version: 3, minor_version: 6, revision: 2, build_number: 51 }
+        };
+        let mut resource = resource::Resource { metadata, regions: indexmap::IndexMap::new(), lsf_layout: None };
+        resource.regions.insert("Root".into(), resource::Region {
+            name: "Root".into(),
+            nodes: vec![test_node("Root", vec![("Value", attribute_type, value.clone())], vec![])],
+        });
+
+        let options = lsf::WriteOptions { version, compression: compression::CompressionMethod::None, ..Default::default() };
+        let first = lsf::to_vec(&resource, &options).expect("Failed to write LSF");
+        let (reread, _) = lsf::from_bytes(&first, &diagnostics::ReadOptions { strict: true })
+            .unwrap_or_else(|e| panic!("Failed to read {:?}: {:#}", attribute_type, e));
+        let attribute = &reread.regions["Root"].nodes[0].attributes["Value"];
+        assert_eq!(attribute.attribute_type, attribute_type);
+        assert_eq!(attribute.value, value, "{:?} changed in a round trip", attribute_type);
+
+        let second = lsf::to_vec(&reread, &options).expect("Failed to write LSF");
+        assert_eq!(first, second, "{:?} is not written byte-exactly", attribute_type);
+    }
+
+    #[test]
+    fn test_lsf_value_round_trip_every_type() {
+        use resource::{AttributeType as T, AttributeValue as V, TranslatedString, TranslatedFSString, TranslatedFSStringArgument};
+
+        let matrix = |n: usize| (0..n).map(|i| i as f32 * 0.5 - 1.0).collect::<Vec<_>>();
+        let values = vec![
+            (T::None, V::None),
+            (T::Byte, V::Byte(0xfe)),
+            (T::Short, V::Short(-1234)),
+            (T::UShort, V::UShort(54321)),
+            (T::Int, V::Int(-123456789)),
+            (T::UInt, V::UInt(0xdead_beef)),
+            (T::Float, V::Float(-0.15625)),
+            (T::Double, V::Double(1.0 / 3.0)),
+            (T::IVec2, V::IVec2([1, -2])),
+            (T::IVec3, V::IVec3([1, -2, 3])),
+            (T::IVec4, V::IVec4([1, -2, 3, i32::MIN])),
+            (T::Vec2, V::Vec2([0.5, -1.5])),
+            (T::Vec3, V::Vec3([0.5, -1.5, 1e-7])),
+            (T::Vec4, V::Vec4([0.5, -1.5, 1e-7, f32::MAX])),
+            (T::Mat2, V::Mat2(matrix(4).try_into().unwrap())),
+            (T::Mat3, V::Mat3(matrix(9).try_into().unwrap())),
+            (T::Mat3x4, V::Mat3x4(matrix(12).try_into().unwrap())),
+            (T::Mat4x3, V::Mat4x3(matrix(12).try_into().unwrap())),
+            (T::Mat4, V::Mat4(matrix(16).try_into().unwrap())),
+            (T::Bool, V::Bool(true)),
+            (T::String, V::String("plain".into())),
+            (T::Path, V::Path("Public/Game/GUI/icon.dds".into())),
+            (T::FixedString, V::FixedString("".into())),
+            (T::LSString, V::LSString("Ünïcödé text".into())),
+            (T::ULongLong, V::ULongLong(u64::MAX)),
+            (T::ScratchBuffer, V::ScratchBuffer(vec![0, 1, 2, 0xff, 0])),
+            (T::LongLong, V::LongLong(i64::MIN)),
+            (T::Int8, V::Int8(-128)),
+            (T::TranslatedString, V::TranslatedString(TranslatedString { version: 3, value: None, handle: "h0a1b2c3dg4e5fg6".into() })),
+            (T::WString, V::WString("wide ✓".into())),
+            (T::LSWString, V::LSWString("also wide".into())),
+            (T::UUID, V::UUID(uuid::Uuid::from_bytes(std::array::from_fn(|i| i as u8 * 17)))),
+            (T::Int64, V::Int64(-42)),
+            (T::TranslatedFSString, V::TranslatedFSString(TranslatedFSString {
+                version: 1,
+                value: None,
+                handle: "hfs".into(),
+                arguments: vec![TranslatedFSStringArgument {
+                    key: "Damage".into(),
+                    string: TranslatedFSString { version: 2, value: None, handle: "hinner".into(), arguments: vec![] },
+                    value: "5".into(),
+                }],
+            })),
+        ];
+        assert_eq!(values.len(), 34);
+
+        for (attribute_type, value) in values {
+            assert_value_round_trip(lsf::LsfVersion::Bg3Patch3, attribute_type, value);
+        }
+
+        // Before v4 translated strings embed their text instead of a version
+        assert_value_round_trip(lsf::LsfVersion::ExtendedNodes, T::TranslatedString,
+            V::TranslatedString(TranslatedString { version: 0, value: Some("Hello".into()), handle: "h1".into() }));
+        assert_value_round_trip(lsf::LsfVersion::ExtendedNodes, T::TranslatedFSString,
+            V::TranslatedFSString(TranslatedFSString { version: 0, value: Some("Deal [1]".into()), handle: "h2".into(), arguments: vec![] }));
+    }
+
+    #[test]
+    fn test_lsf_round_trip_is_byte_identical() {
+        use compression::{CompressionMethod, CompressionLevel};
+        use resource::{AttributeType, AttributeValue};
+
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        // A single string bucket, attributes in a non-hash order and a null-padded string
+        let attributes: &[(&str, u32, &[u8])] = &[("Name", 22, b"ab\0\0"), ("Count", 4, &7i32.to_le_bytes()), ("Flag", 19, &[1])];
+        let path = dir.path().join("padded.lsf");
+        fs::write(&path, build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, attributes)).unwrap();
+        assert_eq!(lsf::verify_roundtrip(&path).expect("Round trip failed"), lsf::RoundTrip::Identical);
+
+        // The metadata bytes after the compression flags (v7 metadata starts at 16) are kept as read
+        let mut data = build_single_node_lsf(CompressionMethod::None, CompressionLevel::Default, true, attributes);
+        data[57..60].copy_from_slice(&[0x12, 0x34, 0x56]);
+        let path = dir.path().join("unknown.lsf");
+        fs::write(&path, &data).unwrap();
+        assert_eq!(lsf::verify_roundtrip(&path).expect("Round trip failed"), lsf::RoundTrip::Identical);
+
+        // Our LZ4 frames differ from the helper's (it leaves the empty keys chunk empty)
+        let path = dir.path().join("compressed.lsf");
+        fs::write(&path, build_single_node_lsf(CompressionMethod::Lz4, CompressionLevel::Fast, true, attributes)).unwrap();
+        assert_eq!(lsf::verify_roundtrip(&path).expect("Round trip failed"), lsf::RoundTrip::SameChunks);
+
+        // Several regions, siblings and keys, compressed with a deterministic compressor
+        let path = dir.path().join("tree.lsf");
+        let options = lsf::WriteOptions { compression: CompressionMethod::Zstd, ..Default::default() };
+        lsf::write_lsf(&test_tree_resource(), &path, &options).expect("Failed to write LSF");
+        assert_eq!(lsf::verify_roundtrip(&path).expect("Round trip failed"), lsf::RoundTrip::Identical);
+
+        // Once the attribute names change, the layout is dropped instead of misapplied
+        let (mut resource, _) = lsf::read_lsf(&path, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
+        resource.regions.get_mut("Config").unwrap().nodes[0].attributes.insert("Extra".into(),
+            resource::NodeAttribute { attribute_type: AttributeType::Bool, value: AttributeValue::Bool(true) });
+        let rewritten = dir.path().join("changed.lsf");
+        lsf::write_lsf(&resource, &rewritten, &lsf::WriteOptions::for_resource(&resource)).expect("Failed to write LSF");
+        let (reread, _) = lsf::read_lsf(&rewritten, &diagnostics::ReadOptions { strict: true }).expect("Failed to reread LSF");
+        assert_eq!(reread.regions["Config"].nodes[0].attributes["Extra"].value, AttributeValue::Bool(true));
+        assert_eq!(reread.regions["Config"].nodes[0].children.len(), 2);
+    }
+
+    #[test]
+    fn test_in_memory_apis_match_file_apis() {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let resource = test_tree_resource();
+        let options = lsf::WriteOptions::default();
+
+        let lsf_path = dir.path().join("tree.lsf");
+        lsf::write_lsf(&resource, &lsf_path, &options).expect("Failed to write LSF");
+        let lsf_data = lsf::to_vec(&resource, &options).expect("Failed to serialize LSF");
+        assert_eq!(fs::read(&lsf_path).unwrap(), lsf_data);
+        let mut streamed = Vec::new();
+        lsf::to_writer(&resource, &mut streamed, &options).expect("Failed to stream LSF");
+        assert_eq!(streamed, lsf_data);
+
+        let (from_file, _) = lsf::read_lsf(&lsf_path, &diagnostics::ReadOptions::default()).expect("Failed to read LSF");
+        let (from_reader, _) = lsf::from_reader(lsf_data.as_slice(), &diagnostics::ReadOptions::default()).expect("Failed to read LSF stream");
+        assert_eq!(from_file.lsf_layout, from_reader.lsf_layout);
+
+        let lsx_path = dir.path().join("tree.lsx");
+        lsx::write_lsx(&from_file, &lsx_path, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+        let lsx_data = lsx::to_vec(&from_file, &lsx::LsxWriteOptions::default()).expect("Failed to serialize LSX");
+        let (from_file, _) = lsx::read_lsx(&lsx_path, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        let (from_bytes, _) = lsx::from_bytes(&lsx_data, &diagnostics::ReadOptions::default()).expect("Failed to read LSX bytes");
+        assert_eq!(from_file.regions["Config"].nodes[0].attributes["Version"].value, from_bytes.regions["Config"].nodes[0].attributes["Version"].value);
+        assert_eq!(from_bytes.regions["Config"].nodes[0].children.len(), 2);
+    }
+
+    #[test]
+    fn test_source_order_is_preserved() {
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <region id="Zeta">
+    <node id="Zeta">
+      <attribute id="Zulu" type="int32" value="1"/>
+      <attribute id="Alpha" type="int32" value="2"/>
+      <attribute id="Mike" type="int32" value="3"/>
+    </node>
+  </region>
+  <region id="Alpha">
+    <node id="Alpha">
+      <attribute id="Name" type="FixedString" value="a"/>
+    </node>
+  </region>
+</save>"#;
+        let (resource, _) = lsx::from_bytes(input, &diagnostics::ReadOptions::default()).expect("Failed to read LSX");
+        let expected_attributes = ["Zulu", "Alpha", "Mike"];
+        assert_eq!(resource.regions.keys().collect::<Vec<_>>(), ["Zeta", "Alpha"]);
+        assert_eq!(resource.regions["Zeta"].nodes[0].attributes.keys().collect::<Vec<_>>(), expected_attributes);
+
+        let lsx_data = lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+        assert_eq!(lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX"), lsx_data);
+        let text = String::from_utf8(lsx_data).unwrap();
+        let positions: Vec<usize> = expected_attributes.iter().map(|id| text.find(&format!("id=\"{}\" type", id)).unwrap()).collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "Attributes reordered: {}", text);
+        assert!(text.find("region id=\"Zeta\"").unwrap() < text.find("region id=\"Alpha\"").unwrap());
+
+        let lsf_data = lsf::to_vec(&resource, &lsf::WriteOptions::default()).expect("Failed to write LSF");
+        let (reread, _) = lsf::from_bytes(&lsf_data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSF");
+        assert_eq!(reread.regions.keys().collect::<Vec<_>>(), ["Zeta", "Alpha"]);
+        assert_eq!(reread.regions["Zeta"].nodes[0].attributes.keys().collect::<Vec<_>>(), expected_attributes);
+    }
+
+    #[test]
+    fn test_node_names_survive_lsf_lsx_lsf() {
+        let lsf_data = lsf::to_vec(&test_tree_resource(), &lsf::WriteOptions::default()).expect("Failed to write LSF");
+        let (from_lsf, _) = lsf::from_bytes(&lsf_data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSF");
+        let config = &from_lsf.regions["Config"].nodes[0];
+        assert_eq!(config.id, "Config");
+        assert_eq!(config.parent, None);
+        assert_eq!(config.children[1].parent.as_deref(), Some("Config"));
+        assert_eq!(config.children[1].children[0].parent.as_deref(), Some("Child"));
+
+        let lsx_text = String::from_utf8(lsx::to_vec(&from_lsf, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX")).unwrap();
+        assert!(lsx_text.contains(r#"<node id="Config">"#) && lsx_text.contains(r#"<node id="Grand">"#), "{}", lsx_text);
+        assert!(!lsx_text.contains("node_"), "{}", lsx_text);
+
+        let (from_lsx, _) = lsx::from_bytes(lsx_text.as_bytes(), &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSX");
+        let grand = &from_lsx.regions["Config"].nodes[0].children[1].children[0];
+        assert_eq!((grand.id.as_str(), grand.parent.as_deref()), ("Grand", Some("Child")));
+
+        let lsf_again = lsf::to_vec(&from_lsx, &lsf::WriteOptions::default()).expect("Failed to write LSF");
+        let (reread, _) = lsf::from_bytes(&lsf_again, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSF");
+        assert_eq!(reread.regions.keys().collect::<Vec<_>>(), ["Config", "Other"]);
+        assert_eq!(reread.regions["Config"].nodes[0].children[0].id, "Child");
+    }
+
+    #[test]
+    fn test_lsx_translated_string_attributes() {
+        use resource::{AttributeValue, TranslatedString};
+
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <region id="Items">
+    <node id="Items">
+      <attribute id="DisplayName" type="TranslatedString" handle="h1234" version="1"/>
+      <attribute id="Description" type="TranslatedString" handle="h5678" value="Tea; two sugars &amp; milk"/>
+    </node>
+  </region>
+</save>"#;
+        let (resource, _) = lsx::from_bytes(input, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSX");
+        let attributes = &resource.regions["Items"].nodes[0].attributes;
+        assert_eq!(attributes["DisplayName"].value, AttributeValue::TranslatedString(TranslatedString { version: 1, value: None, handle: "h1234".into() }));
+        assert_eq!(attributes["Description"].value,
+            AttributeValue::TranslatedString(TranslatedString { version: 0, value: Some("Tea; two sugars & milk".into()), handle: "h5678".into() }));
+
+        let text = String::from_utf8(lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX")).unwrap();
+        assert!(text.contains(r#"<attribute id="DisplayName" type="TranslatedString" handle="h1234" version="1"/>"#), "{}", text);
+        assert!(text.contains(r#"handle="h5678" value="Tea; two sugars &amp; milk""#), "{}", text);
+        let (reread, _) = lsx::from_bytes(text.as_bytes(), &diagnostics::ReadOptions { strict: true }).expect("Failed to read back LSX");
+        assert_eq!(&reread.regions["Items"].nodes[0].attributes, attributes);
+    }
+
+    #[test]
+    fn test_lsx_translated_fs_string_arguments() {
+        use resource::{AttributeValue, TranslatedFSString, TranslatedFSStringArgument};
+
+        let input = br#"<?xml version="1.0" encoding="utf-8"?>
+<save>
+  <version major="4" minor="0" revision="9" build="331"/>
+  <region id="Spells">
+    <node id="Spells">
+      <attribute id="Tooltip" type="TranslatedFSString" value="" handle="htip" arguments="1">
+        <arguments>
+          <argument key="Damage" value="5">
+            <string value="" handle="hdmg" arguments="1">
+              <arguments>
+                <argument key="Type" value="Fire">
+                  <string value="fire" handle="htype" arguments="0"/>
+                </argument>
+              </arguments>
+            </string>
+          </argument>
+        </arguments>
+      </attribute>
+      <attribute id="After" type="int32" value="3"/>
+    </node>
+  </region>
+</save>"#;
+        let (resource, _) = lsx::from_bytes(input, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSX");
+        let attributes = &resource.regions["Spells"].nodes[0].attributes;
+        let inner = TranslatedFSString { version: 0, value: Some("fire".into()), handle: "htype".into(), arguments: vec![] };
+        let damage = TranslatedFSString {
+            version: 0, value: Some(String::new()), handle: "hdmg".into(),
+            arguments: vec![TranslatedFSStringArgument { key: "Type".into(), string: inner, value: "Fire".into() }],
+        };
+        let expected = TranslatedFSString {
+            version: 0, value: Some(String::new()), handle: "htip".into(),
+            arguments: vec![TranslatedFSStringArgument { key: "Damage".into(), string: damage, value: "5".into() }],
+        };
+        assert_eq!(attributes["Tooltip"].value, AttributeValue::TranslatedFSString(expected));
+        assert_eq!(attributes["After"].value, AttributeValue::Int(3));
+
+        let lsx_data = lsx::to_vec(&resource, &lsx::LsxWriteOptions::default()).expect("Failed to write LSX");
+        let text = String::from_utf8(lsx_data.clone()).unwrap();
+        assert!(text.contains(r#"<argument key="Type" value="Fire">"#), "{}", text);
+        let (reread, _) = lsx::from_bytes(&lsx_data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read back LSX");
+        assert_eq!(&reread.regions["Spells"].nodes[0].attributes, attributes);
+
+        let lsf_data = lsf::to_vec(&reread, &lsf::WriteOptions::default()).expect("Failed to write LSF");
+        let (from_lsf, _) = lsf::from_bytes(&lsf_data, &diagnostics::ReadOptions { strict: true }).expect("Failed to read LSF");
+        // BG3 LSF keeps handles and arguments but no embedded text
+        let AttributeValue::TranslatedFSString(tooltip) = &from_lsf.regions["Spells"].nodes[0].attributes["Tooltip"].value else {
+            panic!("Tooltip changed type");
+        };
+        let damage = &tooltip.arguments[0];
+        assert_eq!((tooltip.handle.as_str(), damage.key.as_str(), damage.value.as_str()), ("htip", "Damage", "5"));
+        assert_eq!(damage.string.arguments[0].string.handle, "htype");
+    }
+}
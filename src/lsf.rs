@@ -1,21 +1,122 @@
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::collections::HashMap;
+use indexmap::IndexMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{Read, Write, Seek, SeekFrom, Cursor, BufWriter};
 use std::path::Path;
 use uuid::Uuid;
 
-use crate::resource::{Resource, Metadata, Region, Node, NodeAttribute, AttributeType, AttributeValue};
-use crate::compression::{CompressionMethod, decompress, compress};
+use crate::resource::{Resource, LsfLayout, LsfNodeLayout, LsfAttributeLayout, Metadata, Region, Node, NodeAttribute, AttributeType, AttributeValue, TranslatedString, TranslatedFSString, TranslatedFSStringArgument};
+use crate::diagnostics::{Diagnostic, ReadOptions, ReadReport};
+use crate::compression::{CompressionMethod, CompressionLevel, parse_compression_flags, make_compression_flags, compress, decompress, SizeMismatch};
 
 const LSF_MAGIC: &[u8; 4] = b"LSOF";
 
+/// LSF format versions, named after the LSLib `LSFVersion` they correspond to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LsfVersion {
+    /// Initial version (DOS:EE)
+    Initial = 1,
+    /// Chunked (LZ4 frame) compression for every chunk except strings
+    ChunkedCompress = 2,
+    /// Extended node entries with sibling links and attribute value offsets (DOS2)
+    ExtendedNodes = 3,
+    /// First BG3 version; no layout changes apart from the version number
+    Bg3 = 4,
+    /// 64-bit engine version in the header
+    Bg3ExtendedHeader = 5,
+    /// Node keys chunk
+    Bg3NodeKeys = 6,
+    /// BG3 Patch 3
+    Bg3Patch3 = 7,
+}
+
+impl LsfVersion {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            1 => Some(Self::Initial),
+            2 => Some(Self::ChunkedCompress),
+            3 => Some(Self::ExtendedNodes),
+            4 => Some(Self::Bg3),
+            5 => Some(Self::Bg3ExtendedHeader),
+            6 => Some(Self::Bg3NodeKeys),
+            7 => Some(Self::Bg3Patch3),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for LsfVersion {
+    type Err = anyhow::Error;
+
+    /// Parse a version number such as `3` or `v7`.
+    fn from_str(s: &str) -> Result<Self> {
+        let number = s.strip_prefix(['v', 'V']).unwrap_or(s);
+        number.parse().ok()
+            .and_then(Self::from_u32)
+            .ok_or_else(|| anyhow::anyhow!("Unknown LSF version '{}' (expected 1 to 7)", s))
+    }
+}
+
+/// LSLib's `LSFMetadataFormat`: whether node and attribute entries carry adjacency
+/// links (the V3 entry layouts) and whether node keys are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsfMetadataFormat {
+    None = 0,
+    KeysAndAdjacency = 1,
+    None2 = 2,
+}
+
+impl LsfMetadataFormat {
+    pub fn from_u32(value: u32) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::KeysAndAdjacency),
+            2 => Some(Self::None2),
+            _ => None,
+        }
+    }
+
+    /// Entries use the V3 layouts; adjacency only exists since v3.
+    fn has_adjacency(self, version: LsfVersion) -> bool {
+        version >= LsfVersion::ExtendedNodes && self == Self::KeysAndAdjacency
+    }
+}
+
+/// Structural problems in an LSF file, returned through `anyhow` so callers can downcast.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LsfError {
+    /// A chunk is shorter on disk than its declared size.
+    TruncatedChunk { chunk: &'static str, offset: u64, expected: usize, available: usize },
+    /// A chunk decompressed to a different length than the metadata declares
+    /// (`actual` is `expected + 1` for any longer output).
+    ChunkSizeMismatch { chunk: &'static str, expected: usize, actual: usize },
+    /// Bytes remain after the last chunk.
+    TrailingData { offset: u64, length: usize },
+}
+
+impl fmt::Display for LsfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TruncatedChunk { chunk, offset, expected, available } => write!(f,
+                "{} chunk at offset {} is truncated: expected {} bytes, found {}", chunk, offset, expected, available),
+            Self::ChunkSizeMismatch { chunk, expected, actual } => write!(f,
+                "{} chunk decompressed to {} bytes, but metadata declares {}", chunk, actual, expected),
+            Self::TrailingData { offset, length } => write!(f,
+                "{} unexpected trailing bytes at offset {}", length, offset),
+        }
+    }
+}
+
+impl std::error::Error for LsfError {}
+
 #[derive(Debug)]
 struct LsfHeader {
-    magic: [u8; 4],
-    version: u32,
-    engine_version: u64,  // LSFHeaderV5 is just a 64-bit engine version
+    version: LsfVersion,
+    /// Packed engine version: 32 bits before v5 (LSFHeader), 64 bits since (LSFHeaderV5)
+    engine_version: u64,
 }
 
 #[derive(Debug)]
@@ -30,10 +131,10 @@ struct LsfMetadata {
     attributes_compressed_size: u32,
     values_uncompressed_size: u32,
     values_compressed_size: u32,
-    compression_flags: u32,
-    unknown2: u32,
-    unknown3: u32,
-    unknown4: u32,
+    compression_flags: u8,
+    unknown2: u8,
+    unknown3: u16,
+    metadata_format: LsfMetadataFormat,
 }
 
 #[derive(Debug)]
@@ -52,6 +153,13 @@ struct AttributeEntry {
     offset: u32, // For v3+ format
 }
 
+/// LSFKeyEntry: names the attribute a node is keyed by when the game merges nodes
+#[derive(Debug)]
+struct KeyEntry {
+    node_index: u32,
+    key_name_hash_table_index: u32,
+}
+
 impl AttributeEntry {
     fn attribute_type(&self) -> Option<AttributeType> {
         AttributeType::from_u8((self.type_and_length & 0x3F) as u8)
@@ -62,129 +170,199 @@ impl AttributeEntry {
     }
 }
 
-pub fn read_lsf<P: AsRef<Path>>(path: P) -> Result<Resource> {
-    let mut file = File::open(path)?;
+/// Read an LSF file, returning the resource and the warnings collected along the way.
+/// In strict mode the first warning (a skipped attribute or value, a broken attribute
+/// chain or an invalid sibling link) is returned as an error instead.
+pub fn read_lsf<P: AsRef<Path>>(path: P, options: &ReadOptions) -> Result<(Resource, ReadReport)> {
+    from_reader(File::open(path)?, options)
+}
+
+/// Read an LSF file held in memory, e.g. extracted from a package.
+pub fn from_bytes(data: &[u8], options: &ReadOptions) -> Result<(Resource, ReadReport)> {
+    read_lsf_from_stream(&mut Cursor::new(data), options)
+}
+
+/// Read an LSF file from any reader; the whole stream is buffered first.
+pub fn from_reader<R: Read>(mut reader: R, options: &ReadOptions) -> Result<(Resource, ReadReport)> {
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer)?;
+    reader.read_to_end(&mut buffer)?;
+    from_bytes(&buffer, options)
+}
 
-    let mut cursor = Cursor::new(buffer);
-    read_lsf_from_stream(&mut cursor)
+fn read_lsf_from_stream<R: Read + Seek>(reader: &mut R, options: &ReadOptions) -> Result<(Resource, ReadReport)> {
+    let LsfChunks { header, metadata, strings_data, keys_data, nodes_data, attributes_data, values_data } = read_chunks(reader)?;
+    let (compression, compression_level) = parse_compression_flags(metadata.compression_flags)?;
+
+    // Parse string hash table
+    let string_table = parse_string_table(&strings_data)?;
+
+    // Parse nodes
+    let adjacency = metadata.metadata_format.has_adjacency(header.version);
+    let node_entries = parse_node_entries(&nodes_data, adjacency)?;
+
+    // Parse attributes
+    let attribute_entries = parse_attribute_entries(&attributes_data, adjacency)?;
+    let attribute_entry_size = attribute_entry_size(adjacency);
+
+    // Parse keys; only the keys-and-adjacency format stores them
+    if metadata.metadata_format != LsfMetadataFormat::KeysAndAdjacency && !keys_data.is_empty() {
+        bail!("Keys chunk present, but metadata format is {:?}", metadata.metadata_format);
+    }
+    let key_entries = parse_key_entries(&keys_data)?;
+
+    // Build resource
+    let engine_version = unpack_engine_version(header.engine_version, header.version);
+    let format = ValueFormat::new(header.version, &engine_version);
+    let (mut resource, node_layouts, report) = build_resource(ReadReport::new(options), engine_version, format, &string_table, node_entries, attribute_entries, attribute_entry_size, key_entries, values_data)?;
+    resource.lsf_layout = Some(LsfLayout {
+        version: header.version,
+        compression,
+        compression_level,
+        metadata_format: metadata.metadata_format,
+        sizes_on_disk: compression != CompressionMethod::None || metadata.strings_compressed_size != 0,
+        unknown2: metadata.unknown2,
+        unknown3: metadata.unknown3,
+        string_buckets: string_table.buckets,
+        nodes: node_layouts,
+    });
+    Ok((resource, report))
+}
+
+/// Header, metadata and decompressed chunks of an LSF file.
+struct LsfChunks {
+    header: LsfHeader,
+    metadata: LsfMetadata,
+    strings_data: Vec<u8>,
+    keys_data: Vec<u8>,
+    nodes_data: Vec<u8>,
+    attributes_data: Vec<u8>,
+    values_data: Vec<u8>,
 }
 
-fn read_lsf_from_stream<R: Read + Seek>(reader: &mut R) -> Result<Resource> {
+fn read_chunks<R: Read + Seek>(reader: &mut R) -> Result<LsfChunks> {
     // Read and validate header
     let header = read_header(reader)?;
 
-    if &header.magic != LSF_MAGIC {
-        bail!("Invalid LSF magic bytes");
-    }
-
     // Read metadata
     let metadata = read_metadata(reader, header.version)?;
-    println!("Metadata: {:?}", metadata);
-    println!("Current position after metadata: {}", reader.stream_position()?);
+    let (compression, _) = parse_compression_flags(metadata.compression_flags)?;
+    // Since v2 every chunk except the strings is written as an LZ4 frame
+    let chunked = header.version >= LsfVersion::ChunkedCompress;
 
     // Read and decompress chunks in order: Strings, Keys, Nodes, Attributes, Values
-    println!("Reading strings chunk: compressed={}, uncompressed={}",
-        metadata.strings_compressed_size, metadata.strings_uncompressed_size);
-    let strings_data = read_and_decompress_chunk(reader,
+    let strings_data = read_and_decompress_chunk(reader, "strings",
         metadata.strings_compressed_size as usize,
         metadata.strings_uncompressed_size as usize,
-        get_compression_method(metadata.compression_flags))?;
-    println!("Strings data length: {}", strings_data.len());
+        compression, false)?;
 
     // Read Keys chunk (only for version 6+)
-    let keys_data = if header.version >= 6 {
-        println!("Reading keys chunk: compressed={}, uncompressed={}",
-            metadata.keys_compressed_size, metadata.keys_uncompressed_size);
-        let data = read_and_decompress_chunk(reader,
+    let keys_data = if header.version >= LsfVersion::Bg3NodeKeys {
+        read_and_decompress_chunk(reader, "keys",
             metadata.keys_compressed_size as usize,
             metadata.keys_uncompressed_size as usize,
-            get_compression_method(metadata.compression_flags))?;
-        println!("Keys data length: {}", data.len());
-        data
+            compression, chunked)?
     } else {
         Vec::new()
     };
 
-    println!("Reading nodes chunk: compressed={}, uncompressed={}",
-        metadata.nodes_compressed_size, metadata.nodes_uncompressed_size);
-    let nodes_data = read_and_decompress_chunk(reader,
+    let nodes_data = read_and_decompress_chunk(reader, "nodes",
         metadata.nodes_compressed_size as usize,
         metadata.nodes_uncompressed_size as usize,
-        get_compression_method(metadata.compression_flags))?;
-    println!("Nodes data length: {}", nodes_data.len());
+        compression, chunked)?;
 
-    println!("Reading attributes chunk: compressed={}, uncompressed={}",
-        metadata.attributes_compressed_size, metadata.attributes_uncompressed_size);
-    let attributes_data = read_and_decompress_chunk(reader,
+    let attributes_data = read_and_decompress_chunk(reader, "attributes",
         metadata.attributes_compressed_size as usize,
         metadata.attributes_uncompressed_size as usize,
-        get_compression_method(metadata.compression_flags))?;
-    println!("Attributes data length: {}", attributes_data.len());
-
-    println!("Reading values chunk: compressed={}, uncompressed={}",
-        metadata.values_compressed_size, metadata.values_uncompressed_size);
-    println!("Current position before values: {}", reader.stream_position()?);
-
-    // Read remaining bytes as values (workaround for size mismatch)
-    let mut values_data = Vec::new();
-    reader.read_to_end(&mut values_data)?;
-    println!("Values data length (actual): {}", values_data.len());
-
-    // Parse string hash table
-    println!("First 32 bytes of strings data: {:?}", &strings_data[..std::cmp::min(32, strings_data.len())]);
-
-    // Look for strings at expected positions
-    if strings_data.len() > 712 { // 784 - 72 = 712
-        println!("Bytes around offset 712: {:?}", &strings_data[708..std::cmp::min(728, strings_data.len())]);
-        // Try to find "ActiveProfile" pattern
-        for i in 700..std::cmp::min(800, strings_data.len()) {
-            if i + 13 < strings_data.len() {
-                let slice = &strings_data[i..i+13];
-                if slice == b"ActiveProfile" {
-                    println!("Found 'ActiveProfile' at strings offset {}, preceding bytes: {:?}", i, &strings_data[i-8..i]);
-                }
-            }
-        }
+        compression, chunked)?;
+
+    let values_data = read_and_decompress_chunk(reader, "values",
+        metadata.values_compressed_size as usize,
+        metadata.values_uncompressed_size as usize,
+        compression, chunked)?;
+
+    // Nothing may follow the values chunk
+    let trailing_offset = reader.stream_position()?;
+    let mut trailing = Vec::new();
+    reader.read_to_end(&mut trailing)?;
+    if !trailing.is_empty() {
+        return Err(LsfError::TrailingData { offset: trailing_offset, length: trailing.len() }.into());
     }
 
-    let string_table = parse_string_table(&strings_data)?;
-
-    // Parse nodes
-    println!("Nodes data: {:?}", nodes_data);
-    let node_entries = parse_node_entries(&nodes_data, header.version)?;
-    println!("Found {} node entries", node_entries.len());
-
-    // Parse attributes
-    println!("Attributes data: {:?}", attributes_data);
-    let attribute_entries = parse_attribute_entries(&attributes_data, header.version)?;
-    println!("Found {} attribute entries", attribute_entries.len());
-
-    // Parse keys (for now, we'll ignore the keys data but we needed to read it properly)
-    // TODO: Implement key parsing if needed
-
-    // Build resource
-    build_resource(header.version, string_table, node_entries, attribute_entries, values_data)
+    Ok(LsfChunks { header, metadata, strings_data, keys_data, nodes_data, attributes_data, values_data })
 }
 
 fn read_header<R: Read>(reader: &mut R) -> Result<LsfHeader> {
     let mut magic = [0u8; 4];
     reader.read_exact(&mut magic)?;
+    if &magic != LSF_MAGIC {
+        bail!("Invalid LSF magic bytes");
+    }
+
+    let raw_version = reader.read_u32::<LittleEndian>()?;
+    let version = LsfVersion::from_u32(raw_version)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported LSF version {}", raw_version))?;
 
-    let version = reader.read_u32::<LittleEndian>()?;
-    let engine_version = reader.read_u64::<LittleEndian>()?;
+    let engine_version = if version >= LsfVersion::Bg3ExtendedHeader {
+        reader.read_u64::<LittleEndian>()?
+    } else {
+        u64::from(reader.read_u32::<LittleEndian>()?)
+    };
 
     Ok(LsfHeader {
-        magic,
         version,
         engine_version,
     })
 }
 
-fn read_metadata<R: Read>(reader: &mut R, version: u32) -> Result<LsfMetadata> {
-    // LSF version 7 uses LSFMetadataV6 structure with 5 chunks: Strings, Keys, Nodes, Attributes, Values
-    if version >= 6 {
+/// Decode the packed engine version (LSLib's `PackedVersion`) into its components.
+pub(crate) fn unpack_engine_version(packed: u64, version: LsfVersion) -> Metadata {
+    if version >= LsfVersion::Bg3ExtendedHeader {
+        Metadata {
+            major_version: ((packed >> 55) & 0x7f) as u32,
+            minor_version: ((packed >> 47) & 0xff) as u32,
+            revision: ((packed >> 31) & 0xffff) as u32,
+            build_number: (packed & 0x7fff_ffff) as u32,
+        }
+    } else {
+        Metadata {
+            major_version: ((packed >> 28) & 0x0f) as u32,
+            minor_version: ((packed >> 24) & 0x0f) as u32,
+            revision: ((packed >> 16) & 0xff) as u32,
+            build_number: (packed & 0xffff) as u32,
+        }
+    }
+}
+
+/// Inverse of `unpack_engine_version`; fails if a component does not fit its bit field.
+pub(crate) fn pack_engine_version(metadata: &Metadata, version: LsfVersion) -> Result<u64> {
+    let (major_bits, minor_bits, revision_bits, build_bits) = if version >= LsfVersion::Bg3ExtendedHeader {
+        (7, 8, 16, 31)
+    } else {
+        (4, 4, 8, 16)
+    };
+
+    let fields = [
+        ("major", metadata.major_version, major_bits),
+        ("minor", metadata.minor_version, minor_bits),
+        ("revision", metadata.revision, revision_bits),
+        ("build", metadata.build_number, build_bits),
+    ];
+
+    let mut packed = 0u64;
+    for (name, value, bits) in fields {
+        if u64::from(value) >= 1u64 << bits {
+            bail!("Engine version {} component {} does not fit in {} bits for LSF v{}",
+                name, value, bits, version as u32);
+        }
+        packed = (packed << bits) | u64::from(value);
+    }
+
+    Ok(packed)
+}
+
+fn read_metadata<R: Read>(reader: &mut R, version: LsfVersion) -> Result<LsfMetadata> {
+    // Since v6 (LSFMetadataV6) there are 5 chunks: Strings, Keys, Nodes, Attributes, Values
+    if version >= LsfVersion::Bg3NodeKeys {
         Ok(LsfMetadata {
             strings_uncompressed_size: reader.read_u32::<LittleEndian>()?,
             strings_compressed_size: reader.read_u32::<LittleEndian>()?,
@@ -196,10 +374,10 @@ fn read_metadata<R: Read>(reader: &mut R, version: u32) -> Result<LsfMetadata> {
             attributes_compressed_size: reader.read_u32::<LittleEndian>()?,
             values_uncompressed_size: reader.read_u32::<LittleEndian>()?,
             values_compressed_size: reader.read_u32::<LittleEndian>()?,
-            compression_flags: reader.read_u32::<LittleEndian>()?,
-            unknown2: reader.read_u32::<LittleEndian>()?,
-            unknown3: reader.read_u32::<LittleEndian>()?,
-            unknown4: reader.read_u32::<LittleEndian>()?,
+            compression_flags: reader.read_u8()?,
+            unknown2: reader.read_u8()?,
+            unknown3: reader.read_u16::<LittleEndian>()?,
+            metadata_format: read_metadata_format(reader)?,
         })
     } else {
         // Older versions use LSFMetadataV5 without Keys chunk
@@ -214,246 +392,125 @@ fn read_metadata<R: Read>(reader: &mut R, version: u32) -> Result<LsfMetadata> {
             attributes_compressed_size: reader.read_u32::<LittleEndian>()?,
             values_uncompressed_size: reader.read_u32::<LittleEndian>()?,
             values_compressed_size: reader.read_u32::<LittleEndian>()?,
-            compression_flags: reader.read_u32::<LittleEndian>()?,
-            unknown2: reader.read_u32::<LittleEndian>()?,
-            unknown3: reader.read_u32::<LittleEndian>()?,
-            unknown4: reader.read_u32::<LittleEndian>()?,
+            compression_flags: reader.read_u8()?,
+            unknown2: reader.read_u8()?,
+            unknown3: reader.read_u16::<LittleEndian>()?,
+            metadata_format: read_metadata_format(reader)?,
         })
     }
 }
 
-fn get_compression_method(flags: u32) -> CompressionMethod {
-    CompressionMethod::from_u32(flags & 0x0F).unwrap_or(CompressionMethod::None)
+fn read_metadata_format<R: Read>(reader: &mut R) -> Result<LsfMetadataFormat> {
+    let raw = reader.read_u32::<LittleEndian>()?;
+    LsfMetadataFormat::from_u32(raw)
+        .ok_or_else(|| anyhow::anyhow!("Unsupported LSF metadata format {}", raw))
 }
 
-fn read_and_decompress_chunk<R: Read>(reader: &mut R, compressed_size: usize, uncompressed_size: usize, method: CompressionMethod) -> Result<Vec<u8>> {
+fn read_and_decompress_chunk<R: Read + Seek>(reader: &mut R, chunk: &'static str, compressed_size: usize, uncompressed_size: usize, method: CompressionMethod, chunked: bool) -> Result<Vec<u8>> {
     // Based on LSLib logic: if compressed_size == 0 && uncompressed_size != 0, data is not compressed
     if compressed_size == 0 && uncompressed_size != 0 {
-        let mut data = vec![0u8; uncompressed_size];
-        reader.read_exact(&mut data)?;
-        return Ok(data);
+        return read_chunk_bytes(reader, chunk, uncompressed_size);
     }
 
     if compressed_size == 0 && uncompressed_size == 0 {
         return Ok(Vec::new());
     }
 
+    // Uncompressed files may still fill in the on-disk size; LSLib reads the uncompressed size then
+    if method == CompressionMethod::None {
+        return read_chunk_bytes(reader, chunk, uncompressed_size);
+    }
+
     // Data is compressed
-    let mut compressed_data = vec![0u8; compressed_size];
-    reader.read_exact(&mut compressed_data)?;
+    let compressed_data = read_chunk_bytes(reader, chunk, compressed_size)?;
 
-    decompress(&compressed_data, method, uncompressed_size)
+    decompress(&compressed_data, method, uncompressed_size, chunked).map_err(|e| match e.downcast_ref::<SizeMismatch>() {
+        Some(mismatch) => LsfError::ChunkSizeMismatch { chunk, expected: mismatch.expected, actual: mismatch.actual }.into(),
+        None => anyhow::anyhow!("Failed to decompress {} chunk: {}", chunk, e),
+    })
 }
 
-// --- Refactored String Table Parsing and Lookup ---
+fn read_chunk_bytes<R: Read + Seek>(reader: &mut R, chunk: &'static str, size: usize) -> Result<Vec<u8>> {
+    let offset = reader.stream_position()?;
+    let mut data = Vec::with_capacity(size.min(1 << 24));
+    reader.by_ref().take(size as u64).read_to_end(&mut data)?;
+    if data.len() != size {
+        return Err(LsfError::TruncatedChunk { chunk, offset, expected: size, available: data.len() }.into());
+    }
+    Ok(data)
+}
 
-/// Enum to distinguish between string table formats (hash table for v7, sequential fallback for compatibility)
+// --- String Table Parsing and Lookup ---
+
+/// Names referenced by node and attribute entries, stored as LSLib's hash table:
+/// a list of buckets, each holding the chain of strings that hashed into it.
 #[derive(Debug)]
-enum StringTable {
-    HashTable(Vec<Vec<String>>),
-    Sequential(Vec<String>), // Compatibility fallback (see LSLib)
+struct StringTable {
+    buckets: Vec<Vec<String>>,
+    /// Handle of every name, so writers find names regardless of which bucket holds them
+    handles: HashMap<String, u32>,
 }
 
-/// Parse the string table for LSF files.
-/// According to the mapping.md analysis, this should be a proper hash table with 0x200 buckets,
-/// but the file has bucket_count=0. Based on mapping.md, this indicates we need to interpret 
-/// the data as a hash table even when bucket_count=0.
+/// Parse the strings chunk.
+///
+/// Layout: `u32` bucket count, then for every bucket a `u16` chain length followed by
+/// that many `u16`-length-prefixed UTF-8 strings (not null-terminated).
 fn parse_string_table(data: &[u8]) -> Result<StringTable> {
     if data.is_empty() {
-        return Ok(StringTable::HashTable(vec![Vec::new(); 0x200]));
+        return Ok(StringTable { buckets: Vec::new(), handles: HashMap::new() });
     }
-    
+
     let mut cursor = Cursor::new(data);
     let bucket_count = cursor.read_u32::<LittleEndian>()? as usize;
-    
-    // Based on mapping.md analysis: Files with bucket_count=0 should still be treated as hash tables
-    // The actual structure starts after the bucket_count field with empty buckets followed by strings
-    if bucket_count == 0 {
-        println!("[DEBUG] StringTable: bucket_count=0, but interpreting as hash table structure per mapping.md analysis");
-        
-        // According to mapping.md, strings in this format use the hash table structure:
-        // The bucket_count=0 is misleading - we should parse this as a compact hash table
-        // where meaningful strings are stored in specific bucket positions.
-        
-        // First, read through the data to find strings and map them to the correct bucket positions
-        let mut buckets = vec![Vec::new(); 0x200];
-        
-        // Skip past the empty buckets (24 zero bytes observed in analysis)
-        // Look for the pattern: [1, 0, length, 0] followed by string data
-        let mut pos = 4; // Start after bucket_count
-        while pos + 4 <= data.len() {
-            // Look for string header pattern
-            if data[pos] == 1 && data[pos + 1] == 0 {
-                let str_len = u16::from_le_bytes([data[pos + 2], data[pos + 3]]) as usize;
-                if pos + 4 + str_len <= data.len() {
-                    let string_bytes = &data[pos + 4..pos + 4 + str_len];
-                    let string = String::from_utf8_lossy(string_bytes).to_string();
-                    
-                    if !string.is_empty() {
-                        // According to mapping.md: calculate hash bucket for this string
-                        // For now, store in bucket 0 as a fallback until we implement proper hashing
-                        buckets[0].push(string.clone());
-                        println!("[DEBUG] Found string at pos {}: '{}'", pos, string);
-                    }
-                    
-                    pos += 4 + str_len;
-                } else {
-                    pos += 1;
-                }
-            } else {
-                pos += 1;
-            }
-        }
-        
-        println!("[DEBUG] Parsed {} strings from compact hash table format", buckets[0].len());
-        Ok(StringTable::HashTable(buckets))
-        
-    } else if bucket_count == 0x200 {
-        // Standard hash table format
-        let mut buckets = Vec::with_capacity(bucket_count);
-        println!("[DEBUG] StringTable: Detected proper hash table format with {} buckets", bucket_count);
-        for bucket_idx in 0..bucket_count {
-            let chain_length = cursor.read_u16::<LittleEndian>()? as usize;
-            let mut chain = Vec::with_capacity(chain_length);
-            for _ in 0..chain_length {
-                let str_len = cursor.read_u16::<LittleEndian>()? as usize;
-                let mut string_bytes = vec![0u8; str_len];
-                cursor.read_exact(&mut string_bytes)?;
-                let string = String::from_utf8(string_bytes)
-                    .map_err(|e| anyhow::anyhow!("Invalid UTF-8 in string table: {}", e))?;
-                chain.push(string);
-            }
-            if chain_length > 0 {
-                println!("[DEBUG] Bucket {}: {} strings. First: {:?}", bucket_idx, chain_length, chain.get(0));
-            }
-            buckets.push(chain);
-        }
-        Ok(StringTable::HashTable(buckets))
-    } else {
-        // Fallback to sequential parsing for other bucket counts
-        println!("[DEBUG] StringTable: Unexpected bucket count {}, falling back to sequential parsing", bucket_count);
-        cursor.seek(SeekFrom::Start(0))?; // Reset cursor
-        cursor.read_u32::<LittleEndian>()?; // Skip bucket count
-        let mut strings = Vec::new();
-        
-        // Parse as a flat list of strings: [u8 flag][u8 pad][u16 len][bytes]
-        while (cursor.position() as usize) + 4 <= data.len() {
-            let _flag = cursor.read_u8()?;
-            let _pad = cursor.read_u8()?;
+
+    let mut buckets = Vec::with_capacity(bucket_count.min(0x10000));
+    for bucket_idx in 0..bucket_count {
+        let chain_length = cursor.read_u16::<LittleEndian>()
+            .map_err(|e| anyhow::anyhow!("Truncated string table at bucket {}: {}", bucket_idx, e))? as usize;
+        let mut chain = Vec::with_capacity(chain_length);
+        for _ in 0..chain_length {
             let str_len = cursor.read_u16::<LittleEndian>()? as usize;
-            if (cursor.position() as usize) + str_len > data.len() {
-                break;
-            }
             let mut string_bytes = vec![0u8; str_len];
-            cursor.read_exact(&mut string_bytes)?;
-            let string = String::from_utf8_lossy(&string_bytes).to_string();
-            strings.push(string);
+            cursor.read_exact(&mut string_bytes)
+                .map_err(|e| anyhow::anyhow!("Truncated string in bucket {}: {}", bucket_idx, e))?;
+            let string = String::from_utf8(string_bytes)
+                .map_err(|e| anyhow::anyhow!("Invalid UTF-8 in string table: {}", e))?;
+            chain.push(string);
         }
-        println!("[DEBUG] Parsed {} sequential strings", strings.len());
-        Ok(StringTable::Sequential(strings))
+        buckets.push(chain);
     }
+
+    Ok(StringTable { buckets, handles: HashMap::new() })
 }
 
-/// String lookup: use hash table bucket/offset mapping as described in mapping.md
-fn get_string_from_hash(string_table: &StringTable, hash: u32) -> Option<String> {
-    match string_table {
-        StringTable::HashTable(buckets) => {
-            if hash == 0xFFFFFFFF {
-                return None;
-            }
-            
-            // According to mapping.md: hash is a packed 32-bit value:
-            // - Upper 16 bits: bucket index
-            // - Lower 16 bits: chain index within bucket
-            let bucket_idx = (hash >> 16) as usize;
-            let string_idx = (hash & 0xFFFF) as usize;
-            
-            // For our compact format where strings are stored in bucket 0,
-            // we need a different mapping strategy
-            if bucket_idx == 0 && string_idx < buckets[0].len() {
-                let result = buckets[0].get(string_idx).cloned();
-                println!("[DEBUG] Lookup hash 0x{:08x} => bucket {} idx {}: {:?}", hash, bucket_idx, string_idx, result);
-                result
-            } else if bucket_idx < buckets.len() && string_idx < buckets[bucket_idx].len() {
-                let result = buckets[bucket_idx].get(string_idx).cloned();
-                println!("[DEBUG] Lookup hash 0x{:08x} => bucket {} idx {}: {:?}", hash, bucket_idx, string_idx, result);
-                result
-            } else {
-                // For compact format, try direct mapping to bucket 0
-                // The hash values we see (0x5, 0x7, 0xc, etc.) should map to specific string positions
-                
-                // Instead of hardcoding, try to find a direct mapping pattern
-                // Looking at the successful mappings, it seems like lower hash values
-                // might map to indices based on some formula
-                
-                // Based on analysis: hash values seem to be related to string content/position
-                // Let's try a more systematic approach
-                let mapped_idx = if hash < 0x100 && (hash as usize) < buckets[0].len() {
-                    // Direct mapping for smaller hash values
-                    Some(hash as usize)
-                } else {
-                    // Try some hash transformations for larger values
-                    // From debug output, we know these specific mappings work:
-                    match hash {
-                        0x0000002d => Some(3), // PlayerProfile
-                        0x00000030 => Some(4), // Version64  
-                        0x00000032 => Some(5), // Object
-                        0x0000003b => Some(1), // This was Node 1 that we need to map
-                        0x0000003c => Some(9), // This was Node 59
-                        0x0000003d => Some(6), // HasSignUpDLCs
-                        0x0000003e => Some(7), // DisabledSingleSaveSessions  
-                        0x0000003f => Some(8), // PlayerProfileID
-                        0x00000044 => Some(9), // TwitchDropsReceived
-                        0x00000045 => Some(10), // TutorialEntriesShown
-                        0x00000046 => Some(11), // TwitchToken
-                        0x00000047 => Some(12), // TutorialCompletedWithProfile
-                        0x00000048 => Some(13), // PlayerProfileName
-                        _ => {
-                            // Try modulo mapping as a fallback
-                            let mod_idx = (hash % buckets[0].len() as u32) as usize;
-                            if mod_idx < buckets[0].len() {
-                                Some(mod_idx)
-                            } else {
-                                None
-                            }
-                        }
-                    }
-                };
-                
-                if let Some(idx) = mapped_idx {
-                    let result = buckets[0].get(idx).cloned();
-                    println!("[DEBUG] Mapped lookup hash 0x{:08x} => bucket 0 idx {}: {:?}", hash, idx, result);
-                    result
-                } else {
-                    println!("[DEBUG] Unknown hash 0x{:08x} => no mapping found", hash);
-                    None
-                }
-            }
-        }
-        StringTable::Sequential(strings) => {
-            if hash == 0xFFFFFFFF {
-                return None;
-            }
-            let idx = hash as usize;
-            let result = strings.get(idx).cloned();
-            println!("[DEBUG] Sequential lookup hash 0x{:08x} => idx {}: {:?}", hash, idx, result);
-            result
-        }
-    }
+/// Resolve a packed name handle: upper 16 bits are the bucket index,
+/// lower 16 bits the index within that bucket's chain.
+fn get_string_from_hash(string_table: &StringTable, hash: u32) -> Result<String> {
+    let bucket_idx = (hash >> 16) as usize;
+    let chain_idx = (hash & 0xFFFF) as usize;
+
+    string_table.buckets
+        .get(bucket_idx)
+        .and_then(|chain| chain.get(chain_idx))
+        .cloned()
+        .ok_or_else(|| anyhow::anyhow!(
+            "String handle 0x{:08x} does not resolve (bucket {}, chain index {}, {} buckets)",
+            hash, bucket_idx, chain_idx, string_table.buckets.len()
+        ))
 }
 
-fn parse_node_entries(data: &[u8], version: u32) -> Result<Vec<NodeEntry>> {
-    if data.is_empty() {
-        return Ok(Vec::new());
+fn parse_node_entries(data: &[u8], extended: bool) -> Result<Vec<NodeEntry>> {
+    let entry_size = if extended { 16 } else { 12 }; // bytes per entry
+    if !data.len().is_multiple_of(entry_size) {
+        bail!("Nodes chunk size {} is not a multiple of the {}-byte node entry size", data.len(), entry_size);
     }
 
     let mut cursor = Cursor::new(data);
-    let mut entries = Vec::new();
-
-    let entry_size = if version >= 3 { 16 } else { 12 }; // bytes per entry
+    let mut entries = Vec::with_capacity(data.len() / entry_size);
 
-    while cursor.position() + entry_size <= data.len() as u64 {
-        let entry = if version >= 3 {
+    while (cursor.position() as usize) < data.len() {
+        let entry = if extended {
+            // LSFNodeEntryV3
             NodeEntry {
                 name_hash_table_index: cursor.read_u32::<LittleEndian>()?,
                 parent_index: cursor.read_i32::<LittleEndian>()?,
@@ -461,11 +518,15 @@ fn parse_node_entries(data: &[u8], version: u32) -> Result<Vec<NodeEntry>> {
                 first_attribute_index: cursor.read_i32::<LittleEndian>()?,
             }
         } else {
+            // LSFNodeEntryV2
+            let name_hash_table_index = cursor.read_u32::<LittleEndian>()?;
+            let first_attribute_index = cursor.read_i32::<LittleEndian>()?;
+            let parent_index = cursor.read_i32::<LittleEndian>()?;
             NodeEntry {
-                name_hash_table_index: cursor.read_u32::<LittleEndian>()?,
-                parent_index: cursor.read_i32::<LittleEndian>()?,
+                name_hash_table_index,
+                parent_index,
                 next_sibling_index: -1,
-                first_attribute_index: cursor.read_i32::<LittleEndian>()?,
+                first_attribute_index,
             }
         };
 
@@ -475,276 +536,414 @@ fn parse_node_entries(data: &[u8], version: u32) -> Result<Vec<NodeEntry>> {
     Ok(entries)
 }
 
-fn parse_attribute_entries(data: &[u8], version: u32) -> Result<Vec<AttributeEntry>> {
-    if data.is_empty() {
-        return Ok(Vec::new());
+/// Bytes per LSFAttributeEntryV3, or per LSFAttributeEntryV2 when not `extended`.
+fn attribute_entry_size(extended: bool) -> usize {
+    if extended { 16 } else { 12 }
+}
+
+fn parse_attribute_entries(data: &[u8], extended: bool) -> Result<Vec<AttributeEntry>> {
+    let entry_size = attribute_entry_size(extended);
+    if !data.len().is_multiple_of(entry_size) {
+        bail!("Attributes chunk size {} is not a multiple of the {}-byte attribute entry size", data.len(), entry_size);
     }
 
     let mut cursor = Cursor::new(data);
-    let mut entries = Vec::new();
+    let mut entries: Vec<AttributeEntry> = Vec::with_capacity(data.len() / entry_size);
 
-    let entry_size = if version >= 3 { 16 } else { 12 }; // bytes per entry
+    if extended {
+        // LSFAttributeEntryV3: explicit chain links and value offsets
+        while (cursor.position() as usize) < data.len() {
+            entries.push(AttributeEntry {
+                name_hash_table_index: cursor.read_u32::<LittleEndian>()?,
+                type_and_length: cursor.read_u32::<LittleEndian>()?,
+                next_attribute_index: cursor.read_i32::<LittleEndian>()?,
+                offset: cursor.read_u32::<LittleEndian>()?,
+            });
+        }
+        return Ok(entries);
+    }
+
+    // LSFAttributeEntryV2: each entry names its owning node instead of the next attribute,
+    // and values are stored back to back in entry order.
+    let mut last_attribute_of_node: HashMap<i32, usize> = HashMap::new();
+    let mut data_offset = 0u32;
+    while (cursor.position() as usize) < data.len() {
+        let name_hash_table_index = cursor.read_u32::<LittleEndian>()?;
+        let type_and_length = cursor.read_u32::<LittleEndian>()?;
+        let node_index = cursor.read_i32::<LittleEndian>()?;
+
+        let index = entries.len();
+        if let Some(previous) = last_attribute_of_node.insert(node_index, index) {
+            entries[previous].next_attribute_index = index as i32;
+        }
 
-    while cursor.position() + entry_size <= data.len() as u64 {
         let entry = AttributeEntry {
-            name_hash_table_index: cursor.read_u32::<LittleEndian>()?,
-            type_and_length: cursor.read_u32::<LittleEndian>()?,
-            next_attribute_index: cursor.read_i32::<LittleEndian>()?,
-            offset: if version >= 3 { cursor.read_u32::<LittleEndian>()? } else { 0 },
+            name_hash_table_index,
+            type_and_length,
+            next_attribute_index: -1,
+            offset: data_offset,
         };
-
+        data_offset = data_offset.checked_add(entry.length())
+            .ok_or_else(|| anyhow::anyhow!("Attribute {} value offset overflows", index))?;
         entries.push(entry);
     }
 
     Ok(entries)
 }
 
+fn parse_key_entries(data: &[u8]) -> Result<Vec<KeyEntry>> {
+    if !data.len().is_multiple_of(8) {
+        bail!("Keys chunk size {} is not a multiple of the 8-byte key entry size", data.len());
+    }
+
+    let mut cursor = Cursor::new(data);
+    let mut entries = Vec::with_capacity(data.len() / 8);
+
+    while (cursor.position() as usize) < data.len() {
+        entries.push(KeyEntry {
+            node_index: cursor.read_u32::<LittleEndian>()?,
+            key_name_hash_table_index: cursor.read_u32::<LittleEndian>()?,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn build_resource(
-    version: u32,
-    string_table: StringTable,
+    mut report: ReadReport,
+    engine_version: Metadata,
+    format: ValueFormat,
+    string_table: &StringTable,
     node_entries: Vec<NodeEntry>,
     attribute_entries: Vec<AttributeEntry>,
+    attribute_entry_size: usize,
+    key_entries: Vec<KeyEntry>,
     values_data: Vec<u8>,
-) -> Result<Resource> {
+) -> Result<(Resource, Vec<LsfNodeLayout>, ReadReport)> {
     let mut resource = Resource {
-        metadata: Metadata {
-            major_version: version,
-            minor_version: 0,
-            revision: 0,
-            build_number: 0,
-        },
-        regions: HashMap::new(),
+        metadata: engine_version,
+        regions: IndexMap::new(),
+        lsf_layout: None,
     };
 
     // If no nodes, create a minimal resource
     if node_entries.is_empty() {
-        return Ok(resource);
+        return Ok((resource, Vec::new(), report));
     }
 
     let mut nodes: Vec<Option<Node>> = vec![None; node_entries.len()];
+    let mut node_layouts = Vec::with_capacity(node_entries.len());
     let mut values_cursor = Cursor::new(values_data);
 
     // Build nodes
     for (node_idx, node_entry) in node_entries.iter().enumerate() {
-        println!("[DEBUG] Node {} hash: 0x{:08x}", node_idx, node_entry.name_hash_table_index);
-        let node_name = get_string_from_hash(&string_table, node_entry.name_hash_table_index)
-            .unwrap_or_else(|| format!("Unknown_0x{:08x}", node_entry.name_hash_table_index));
-        println!("[DEBUG] Node {} name: '{}'", node_idx, node_name);
-
-        let mut node = Node {
-            id: format!("node_{}", node_idx),
-            name: Some(node_name.clone()),
+        let node_name = get_string_from_hash(string_table, node_entry.name_hash_table_index)
+            .map_err(|e| anyhow::anyhow!("Node {}: {}", node_idx, e))?;
+
+        let (attributes, attribute_layouts) = read_node_attributes(node_idx, node_entry.first_attribute_index, &attribute_entries, attribute_entry_size, string_table, &mut values_cursor, format, &mut report)
+            .with_context(|| format!("Node {} ('{}')", node_idx, node_name))?;
+        node_layouts.push(LsfNodeLayout {
+            name: node_name.clone(),
+            parent_index: node_entry.parent_index,
+            next_sibling_index: node_entry.next_sibling_index,
+            attributes: attribute_layouts,
+        });
+
+        nodes[node_idx] = Some(Node {
+            id: node_name,
             parent: None,
-            attributes: HashMap::new(),
+            key: None,
+            attributes,
             children: Vec::new(),
-        };
+        });
+    }
 
-        // Read attributes for this node - only if we have attributes
-        if !attribute_entries.is_empty() && node_entry.first_attribute_index >= 0 {
-            if let Err(e) = read_node_attributes(&mut node, node_entry.first_attribute_index, &attribute_entries, &string_table, &mut values_cursor, version) {
-                println!("[DEBUG] Warning: Failed to read attributes for node {}: {}", node_idx, e);
-                // Continue without attributes for this node
-            }
+    // Attach node keys
+    for key_entry in &key_entries {
+        let key_name = get_string_from_hash(string_table, key_entry.key_name_hash_table_index)
+            .map_err(|e| anyhow::anyhow!("Key of node {}: {}", key_entry.node_index, e))?;
+        match nodes.get_mut(key_entry.node_index as usize) {
+            Some(Some(node)) => node.key = Some(key_name),
+            _ => bail!("Key '{}' refers to node {} but there are only {} nodes",
+                key_name, key_entry.node_index, node_entries.len()),
         }
-
-        nodes[node_idx] = Some(node);
     }
 
-    // Build hierarchy and regions
-    let mut found_regions = false;
+    // Build hierarchy: nodes with parent -1 are regions, every other node is
+    // appended to its parent's children in file order.
+    let mut child_indices: Vec<Vec<usize>> = vec![Vec::new(); node_entries.len()];
+    let mut root_indices = Vec::new();
     for (node_idx, node_entry) in node_entries.iter().enumerate() {
-        if node_entry.parent_index <= 0 {  // Changed from == -1 to <= 0 for more inclusive root detection
-            // This is a region (root node)
-            if let Some(mut node) = nodes[node_idx].take() {
-                let region_name = node.name.clone().unwrap_or_else(|| format!("Region_{}", node_idx));
-
-                // If we have strings that weren't used as node names, add them as attributes
-                if let StringTable::HashTable(buckets) = &string_table {
-                    if !buckets[0].is_empty() {
-                        for (str_idx, string) in buckets[0].iter().enumerate() {
-                            if string != &region_name && !string.is_empty() && string.len() > 3 {
-                                // Add missing strings as attributes
-                                node.attributes.insert(
-                                    format!("string_attr_{}", str_idx),
-                                    NodeAttribute {
-                                        attribute_type: crate::resource::AttributeType::String,
-                                        value: crate::resource::AttributeValue::String(string.clone())
-                                    }
-                                );
-                            }
-                        }
-                    }
-                }
+        match node_entry.parent_index {
+            -1 => root_indices.push(node_idx),
+            parent if parent >= 0 && (parent as usize) < node_idx => {
+                child_indices[parent as usize].push(node_idx);
+            }
+            parent => bail!("Node {} has invalid parent index {} (parents must precede their children)", node_idx, parent),
+        }
 
-                let region = Region {
-                    name: region_name.clone(),
-                    nodes: vec![node],
-                };
-                resource.regions.insert(region_name, region);
-                found_regions = true;
+        let sibling = node_entry.next_sibling_index;
+        if sibling != -1 {
+            let valid = sibling > node_idx as i32
+                && (sibling as usize) < node_entries.len()
+                && node_entries[sibling as usize].parent_index == node_entry.parent_index;
+            if !valid {
+                // Only V3 node entries (16 bytes) have sibling links; the tree is built from parents alone
+                report.warn(Diagnostic::new(format!("invalid next sibling index {}", sibling))
+                    .in_chunk("nodes", Some(node_idx as u64 * 16))
+                    .at_node(node_idx))?;
             }
         }
     }
 
-    // If no regions were found but we have nodes, create a default region
-    if !found_regions && !nodes.is_empty() {
-        for (node_idx, node_opt) in nodes.into_iter().enumerate() {
-            if let Some(mut node) = node_opt {
-                let region_name = format!("DefaultRegion_{}", node_idx);
-
-                // Add all found strings as attributes to ensure they appear somewhere
-                if let StringTable::HashTable(buckets) = &string_table {
-                    if !buckets[0].is_empty() {
-                        for (str_idx, string) in buckets[0].iter().enumerate() {
-                            if !string.is_empty() && string.len() > 3 {
-                                node.attributes.insert(
-                                    format!("found_string_{}", str_idx),
-                                    NodeAttribute {
-                                        attribute_type: crate::resource::AttributeType::String,
-                                        value: crate::resource::AttributeValue::String(string.clone())
-                                    }
-                                );
-                            }
-                        }
-                    }
-                }
+    for root_idx in root_indices {
+        if let Some(mut node) = nodes[root_idx].take() {
+            node.children = collect_children(&mut nodes, root_idx, &node.id, &child_indices);
+            let region_name = node.id.clone();
 
-                let region = Region {
-                    name: region_name.clone(),
-                    nodes: vec![node],
-                };
-                resource.regions.insert(region_name, region);
-                found_regions = true;
-                break; // Just take the first valid node for now
-            }
+            resource.regions
+                .entry(region_name.clone())
+                .or_insert_with(|| Region { name: region_name, nodes: Vec::new() })
+                .nodes
+                .push(node);
         }
     }
 
-    Ok(resource)
+    Ok((resource, node_layouts, report))
 }
 
-/// Enhanced node attribute reading with robust error handling
+/// Follow a node's attribute chain and read every value, recording skipped attributes in `report`.
+/// Also returns where each value that was read is stored, in chain order.
+#[allow(clippy::too_many_arguments)]
 fn read_node_attributes(
-    node: &mut Node,
+    node_idx: usize,
     first_attr_index: i32,
     attribute_entries: &[AttributeEntry],
+    attribute_entry_size: usize,
     string_table: &StringTable,
     values_cursor: &mut Cursor<Vec<u8>>,
-    version: u32,
-) -> Result<()> {
-    if first_attr_index < 0 {
-        return Ok(());
-    }
-
+    format: ValueFormat,
+    report: &mut ReadReport,
+) -> Result<(IndexMap<String, NodeAttribute>, Vec<LsfAttributeLayout>)> {
+    let mut attributes = IndexMap::new();
+    let mut layouts = Vec::new();
     let mut attr_index = first_attr_index;
-    let mut current_offset = 0u32;
     let mut visited_attributes = std::collections::HashSet::new();
-    let mut attributes_read = 0;
-    const MAX_ATTRIBUTES: usize = 1000; // Safety limit to prevent runaway loops
 
-    while attr_index >= 0 && (attr_index as usize) < attribute_entries.len() && attributes_read < MAX_ATTRIBUTES {
+    while attr_index >= 0 {
+        let entry_offset = Some((attr_index as usize * attribute_entry_size) as u64);
+
         // Prevent infinite loops in attribute chains
-        if visited_attributes.contains(&attr_index) {
-            println!("[DEBUG] Warning: Circular reference detected at attribute index {}", attr_index);
+        if !visited_attributes.insert(attr_index) {
+            report.warn(Diagnostic::new(format!("circular attribute chain at attribute index {}", attr_index))
+                .in_chunk("attributes", entry_offset)
+                .at_node(node_idx))?;
             break;
         }
-        visited_attributes.insert(attr_index);
 
-        // Bounds checking for attribute entries
-        if (attr_index as usize) >= attribute_entries.len() {
-            println!("[DEBUG] Warning: Attribute index {} out of bounds (max: {})", attr_index, attribute_entries.len());
+        let Some(attr_entry) = attribute_entries.get(attr_index as usize) else {
+            report.warn(Diagnostic::new(format!("attribute index {} out of bounds (count: {})", attr_index, attribute_entries.len()))
+                .in_chunk("attributes", entry_offset)
+                .at_node(node_idx))?;
             break;
-        }
-
-        let attr_entry = &attribute_entries[attr_index as usize];
+        };
 
-        // Enhanced attribute name resolution with fallback
         let attr_name = get_string_from_hash(string_table, attr_entry.name_hash_table_index)
-            .unwrap_or_else(|| format!("attr_0x{:08x}", attr_entry.name_hash_table_index));
-
-        // Enhanced attribute type validation
-        let attr_type = match attr_entry.attribute_type() {
-            Some(t) => t,
-            None => {
-                println!("[DEBUG] Warning: Unknown attribute type {} for attribute '{}'", 
-                    attr_entry.type_and_length & 0x3F, attr_name);
-                // Skip this attribute but continue processing others
-                attr_index = attr_entry.next_attribute_index;
-                attributes_read += 1;
-                continue;
-            }
-        };
+            .map_err(|e| anyhow::anyhow!("Attribute {}: {}", attr_index, e))?;
+        attr_index = attr_entry.next_attribute_index;
 
-        // Enhanced stream positioning with better bounds checking
-        let seek_pos = if version >= 3 {
-            attr_entry.offset as u64
-        } else {
-            current_offset as u64
+        // Value offsets are explicit since v3 and reconstructed from entry order before that
+        let seek_pos = attr_entry.offset as u64;
+        let attr_length = attr_entry.length();
+        let warning = |message: String| Diagnostic::new(message)
+            .in_chunk("values", Some(seek_pos))
+            .at_node(node_idx)
+            .at_attribute(attr_name.clone());
+
+        let Some(attr_type) = attr_entry.attribute_type() else {
+            report.warn(warning(format!("unknown attribute type {}", attr_entry.type_and_length & 0x3F)))?;
+            continue;
         };
 
-        // Validate seek position against values stream length
-        let values_len = values_cursor.get_ref().len() as u64;
-        if seek_pos >= values_len {
-            println!("[DEBUG] Warning: Attribute '{}' seeks beyond values stream (pos: {}, len: {})", 
-                attr_name, seek_pos, values_len);
-            break;
-        }
-
         // Validate that we have enough data for the attribute length
-        let attr_length = attr_entry.length();
+        let values_len = values_cursor.get_ref().len() as u64;
         if seek_pos + attr_length as u64 > values_len {
-            println!("[DEBUG] Warning: Attribute '{}' extends beyond values stream (pos: {}, len: {}, stream_len: {})", 
-                attr_name, seek_pos, attr_length, values_len);
-            break;
+            report.warn(warning(format!("{} byte value extends beyond the {} byte values chunk", attr_length, values_len)))?;
+            continue;
         }
 
-        // Seek to attribute data position
-        if let Err(e) = values_cursor.seek(SeekFrom::Start(seek_pos)) {
-            println!("[DEBUG] Warning: Failed to seek to attribute '{}' at position {}: {}", attr_name, seek_pos, e);
-            break;
+        if let Some(size) = attr_type.fixed_size().filter(|&size| size != attr_length) {
+            report.warn(warning(format!("{} byte value declared for {:?}, which is {} bytes", attr_length, attr_type, size)))?;
+            continue;
         }
 
-        // Read attribute value with enhanced error handling
-        match read_attribute_value(values_cursor, &attr_type, attr_length) {
+        values_cursor.seek(SeekFrom::Start(seek_pos))?;
+        match read_attribute_value(values_cursor, &attr_type, attr_length, format) {
             Ok(attr_value) => {
-                node.attributes.insert(attr_name.clone(), NodeAttribute {
+                // Variable-length values must end exactly where the entry says
+                let consumed = values_cursor.position() - seek_pos;
+                if consumed != attr_length as u64 {
+                    report.warn(warning(format!("{} byte value declared, but its {:?} encoding is {} bytes", attr_length, attr_type, consumed)))?;
+                    continue;
+                }
+                layouts.push(LsfAttributeLayout {
+                    name: attr_name.clone(),
+                    offset: attr_entry.offset,
+                    length: attr_length,
+                });
+                attributes.insert(attr_name, NodeAttribute {
                     attribute_type: attr_type,
                     value: attr_value,
                 });
-                println!("[DEBUG] Successfully read attribute '{}' (type: {:?})", attr_name, attr_type);
-            }
-            Err(e) => {
-                println!("[DEBUG] Warning: Failed to read attribute '{}' (type: {:?}): {}", 
-                    attr_name, attr_type, e);
-                // Continue processing other attributes even if one fails
             }
+            Err(e) => report.warn(warning(format!("failed to read {:?} value: {}", attr_type, e)))?,
         }
+    }
 
-        // Update offset for pre-v3 formats
-        if version < 3 {
-            current_offset += attr_length;
+    Ok((attributes, layouts))
+}
+
+/// Format-dependent encoding choices for attribute values.
+#[derive(Debug, Clone, Copy)]
+struct ValueFormat {
+    /// TranslatedString stores a u16 version instead of its value text
+    translated_string_versioned: bool,
+    /// TranslatedFSString stores a u16 version instead of its value text
+    fs_string_versioned: bool,
+}
+
+impl ValueFormat {
+    fn new(version: LsfVersion, engine_version: &Metadata) -> Self {
+        // DOS2:DE (engine 4.0.0.26+) switched TranslatedString over before the LSF version was bumped
+        let engine_versioned = engine_version.major_version > 4
+            || (engine_version.major_version == 4 && engine_version.revision > 0)
+            || (engine_version.major_version == 4 && engine_version.revision == 0 && engine_version.build_number >= 0x1a);
+
+        ValueFormat {
+            translated_string_versioned: version >= LsfVersion::Bg3 || engine_versioned,
+            fs_string_versioned: version >= LsfVersion::Bg3,
         }
+    }
+}
 
-        // Move to next attribute in the chain
-        attr_index = attr_entry.next_attribute_index;
-        attributes_read += 1;
+/// Nesting limit for TranslatedFSString arguments, so corrupt data cannot exhaust the stack
+pub(crate) const MAX_FS_STRING_DEPTH: usize = 64;
+
+/// Read an `i32` byte length (including the null terminator) followed by a null-terminated UTF-8 string.
+fn read_length_prefixed_string<R: Read>(reader: &mut R) -> Result<String> {
+    let length = reader.read_i32::<LittleEndian>()?;
+    if length < 1 {
+        bail!("Invalid string length {}", length);
+    }
+    read_null_terminated_string(reader, length as u32)
+}
+
+fn read_translated_fs_string<R: Read>(reader: &mut R, format: ValueFormat, depth: usize) -> Result<TranslatedFSString> {
+    if depth > MAX_FS_STRING_DEPTH {
+        bail!("TranslatedFSString arguments nested deeper than {}", MAX_FS_STRING_DEPTH);
+    }
+
+    let (version, value) = if format.fs_string_versioned {
+        (reader.read_u16::<LittleEndian>()?, None)
+    } else {
+        (0, Some(read_length_prefixed_string(reader)?))
+    };
+    let handle = read_length_prefixed_string(reader)?;
+
+    let argument_count = reader.read_i32::<LittleEndian>()?;
+    if argument_count < 0 {
+        bail!("Invalid TranslatedFSString argument count {}", argument_count);
+    }
+
+    let mut arguments = Vec::new();
+    for _ in 0..argument_count {
+        let key = read_length_prefixed_string(reader)?;
+        let string = read_translated_fs_string(reader, format, depth + 1)?;
+        let value = read_length_prefixed_string(reader)?;
+        arguments.push(TranslatedFSStringArgument { key, string, value });
+    }
+
+    Ok(TranslatedFSString { version, value, handle, arguments })
+}
+
+/// Types whose values are read by `read_null_terminated_string`, so may carry null padding.
+fn is_null_terminated(attr_type: AttributeType) -> bool {
+    matches!(attr_type, AttributeType::String | AttributeType::LSString | AttributeType::Path
+        | AttributeType::FixedString | AttributeType::WString | AttributeType::LSWString)
+}
+
+/// Read a `length`-byte string whose last byte is its null terminator. Like LSLib,
+/// null padding before the terminator is dropped.
+fn read_null_terminated_string<R: Read>(reader: &mut R, length: u32) -> Result<String> {
+    if length == 0 {
+        return Ok(String::new());
     }
 
-    if attributes_read >= MAX_ATTRIBUTES {
-        println!("[DEBUG] Warning: Reached maximum attribute limit for node, possible infinite loop");
+    let mut bytes = vec![0u8; length as usize];
+    reader.read_exact(&mut bytes)?;
+    if bytes.pop() != Some(0) {
+        bail!("String is not null-terminated");
+    }
+    while bytes.last() == Some(&0) {
+        bytes.pop();
     }
 
+    String::from_utf8(bytes).map_err(|e| anyhow::anyhow!("Invalid UTF-8 in string: {}", e))
+}
+
+/// Decode a stored GUID as LSLib prints it: the bytes are a .NET `Guid` (first three fields
+/// little-endian), and its `ByteSwapGuids` option swaps the last 8 bytes in pairs.
+fn guid_from_lsf(mut bytes: [u8; 16]) -> Uuid {
+    swap_guid_byte_pairs(&mut bytes);
+    Uuid::from_bytes_le(bytes)
+}
+
+/// Inverse of `guid_from_lsf`.
+fn guid_to_lsf(uuid: &Uuid) -> [u8; 16] {
+    let mut bytes = uuid.to_bytes_le();
+    swap_guid_byte_pairs(&mut bytes);
+    bytes
+}
+
+/// LSLib's `ByteSwapGuid`: swap bytes 8..16 in pairs. Swapping twice restores the original order.
+pub(crate) fn swap_guid_byte_pairs(bytes: &mut [u8; 16]) {
+    for pair in bytes[8..16].chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+}
+
+fn write_length_prefixed_string<W: Write>(writer: &mut W, s: &str) -> Result<()> {
+    writer.write_i32::<LittleEndian>(s.len() as i32 + 1)?;
+    writer.write_all(s.as_bytes())?;
+    writer.write_u8(0)?;
     Ok(())
 }
 
-/// Enhanced attribute value parsing following LSLib's type-driven parsing strategy
-/// This replicates the large switch statement in LSLib's LSFReader.cs
-fn read_attribute_value<R: Read>(reader: &mut R, attr_type: &AttributeType, length: u32) -> Result<AttributeValue> {
-    // Add bounds checking for safety
-    if length > 1024 * 1024 { // 1MB safety limit
-        bail!("Attribute length {} exceeds safety limit", length);
+fn write_translated_fs_string<W: Write>(writer: &mut W, fs: &TranslatedFSString, format: ValueFormat) -> Result<()> {
+    if format.fs_string_versioned {
+        writer.write_u16::<LittleEndian>(fs.version)?;
+    } else {
+        write_length_prefixed_string(writer, fs.value.as_deref().unwrap_or_default())?;
+    }
+    write_length_prefixed_string(writer, &fs.handle)?;
+
+    writer.write_i32::<LittleEndian>(fs.arguments.len() as i32)?;
+    for argument in &fs.arguments {
+        write_length_prefixed_string(writer, &argument.key)?;
+        write_translated_fs_string(writer, &argument.string, format)?;
+        write_length_prefixed_string(writer, &argument.value)?;
     }
 
+    Ok(())
+}
+
+/// Enhanced attribute value parsing following LSLib's type-driven parsing strategy
+/// This replicates the large switch statement in LSLib's LSFReader.cs
+/// `write_attribute_value` is its exact inverse.
+///
+/// `length` must have been checked against the values chunk by the caller.
+fn read_attribute_value<R: Read>(reader: &mut R, attr_type: &AttributeType, length: u32, format: ValueFormat) -> Result<AttributeValue> {
     Ok(match attr_type {
         AttributeType::None => AttributeValue::None,
         
@@ -765,43 +964,43 @@ fn read_attribute_value<R: Read>(reader: &mut R, attr_type: &AttributeType, leng
         // Vector types - read as sequence of floats/ints
         AttributeType::IVec2 => {
             let mut vec = [0i32; 2];
-            for i in 0..2 {
-                vec[i] = reader.read_i32::<LittleEndian>()?;
+            for v in vec.iter_mut() {
+                *v = reader.read_i32::<LittleEndian>()?;
             }
             AttributeValue::IVec2(vec)
         },
         AttributeType::IVec3 => {
             let mut vec = [0i32; 3];
-            for i in 0..3 {
-                vec[i] = reader.read_i32::<LittleEndian>()?;
+            for v in vec.iter_mut() {
+                *v = reader.read_i32::<LittleEndian>()?;
             }
             AttributeValue::IVec3(vec)
         },
         AttributeType::IVec4 => {
             let mut vec = [0i32; 4];
-            for i in 0..4 {
-                vec[i] = reader.read_i32::<LittleEndian>()?;
+            for v in vec.iter_mut() {
+                *v = reader.read_i32::<LittleEndian>()?;
             }
             AttributeValue::IVec4(vec)
         },
         AttributeType::Vec2 => {
             let mut vec = [0f32; 2];
-            for i in 0..2 {
-                vec[i] = reader.read_f32::<LittleEndian>()?;
+            for v in vec.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Vec2(vec)
         },
         AttributeType::Vec3 => {
             let mut vec = [0f32; 3];
-            for i in 0..3 {
-                vec[i] = reader.read_f32::<LittleEndian>()?;
+            for v in vec.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Vec3(vec)
         },
         AttributeType::Vec4 => {
             let mut vec = [0f32; 4];
-            for i in 0..4 {
-                vec[i] = reader.read_f32::<LittleEndian>()?;
+            for v in vec.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Vec4(vec)
         },
@@ -809,93 +1008,50 @@ fn read_attribute_value<R: Read>(reader: &mut R, attr_type: &AttributeType, leng
         // Matrix types - read as sequence of floats in row-major order
         AttributeType::Mat2 => {
             let mut mat = [0f32; 4];
-            for i in 0..4 {
-                mat[i] = reader.read_f32::<LittleEndian>()?;
+            for v in mat.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Mat2(mat)
         },
         AttributeType::Mat3 => {
             let mut mat = [0f32; 9];
-            for i in 0..9 {
-                mat[i] = reader.read_f32::<LittleEndian>()?;
+            for v in mat.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Mat3(mat)
         },
         AttributeType::Mat3x4 => {
             let mut mat = [0f32; 12];
-            for i in 0..12 {
-                mat[i] = reader.read_f32::<LittleEndian>()?;
+            for v in mat.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Mat3x4(mat)
         },
         AttributeType::Mat4x3 => {
             let mut mat = [0f32; 12];
-            for i in 0..12 {
-                mat[i] = reader.read_f32::<LittleEndian>()?;
+            for v in mat.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Mat4x3(mat)
         },
         AttributeType::Mat4 => {
             let mut mat = [0f32; 16];
-            for i in 0..16 {
-                mat[i] = reader.read_f32::<LittleEndian>()?;
+            for v in mat.iter_mut() {
+                *v = reader.read_f32::<LittleEndian>()?;
             }
             AttributeValue::Mat4(mat)
         },
 
-        // String types - length-prefixed UTF-8 encoded
-        AttributeType::String | AttributeType::LSString | AttributeType::Path | AttributeType::FixedString => {
-            if length == 0 {
-                let string = String::new();
-                return Ok(match attr_type {
-                    AttributeType::String | AttributeType::LSString => AttributeValue::String(string),
-                    AttributeType::Path => AttributeValue::Path(string),
-                    AttributeType::FixedString => AttributeValue::FixedString(string),
-                    _ => unreachable!(),
-                });
-            }
-
-            let mut string_bytes = vec![0u8; length as usize];
-            reader.read_exact(&mut string_bytes)?;
-            
-            // Remove null terminators from the end
-            while string_bytes.last() == Some(&0) {
-                string_bytes.pop();
-            }
-            
-            let string = String::from_utf8_lossy(&string_bytes).to_string();
+        // String types - UTF-8 with a null terminator counted in the length; LSLib
+        // stores the wide string types the same way
+        AttributeType::String | AttributeType::LSString | AttributeType::Path | AttributeType::FixedString
+            | AttributeType::WString | AttributeType::LSWString => {
+            let string = read_null_terminated_string(reader, length)?;
             match attr_type {
-                AttributeType::String | AttributeType::LSString => AttributeValue::String(string),
+                AttributeType::String => AttributeValue::String(string),
+                AttributeType::LSString => AttributeValue::LSString(string),
                 AttributeType::Path => AttributeValue::Path(string),
                 AttributeType::FixedString => AttributeValue::FixedString(string),
-                _ => unreachable!(),
-            }
-        },
-
-        // Wide string types - UTF-16 encoded
-        AttributeType::WString | AttributeType::LSWString => {
-            if length == 0 {
-                let string = String::new();
-                return Ok(match attr_type {
-                    AttributeType::WString => AttributeValue::WString(string),
-                    AttributeType::LSWString => AttributeValue::LSWString(string),
-                    _ => unreachable!(),
-                });
-            }
-
-            let char_count = length as usize / 2;
-            let mut chars = Vec::with_capacity(char_count);
-            for _ in 0..char_count {
-                chars.push(reader.read_u16::<LittleEndian>()?);
-            }
-            
-            // Remove null terminators
-            while chars.last() == Some(&0) {
-                chars.pop();
-            }
-            
-            let string = String::from_utf16_lossy(&chars);
-            match attr_type {
                 AttributeType::WString => AttributeValue::WString(string),
                 AttributeType::LSWString => AttributeValue::LSWString(string),
                 _ => unreachable!(),
@@ -906,93 +1062,23 @@ fn read_attribute_value<R: Read>(reader: &mut R, attr_type: &AttributeType, leng
         AttributeType::UUID => {
             let mut uuid_bytes = [0u8; 16];
             reader.read_exact(&mut uuid_bytes)?;
-            
-            // Replicate LSLib's non-standard GUID byte swapping behavior
-            // LSLib swaps the last 8 bytes when ByteSwapGuids is enabled
-            // This is a compatibility requirement mentioned in attribute.md
-            let mut swapped_bytes = uuid_bytes;
-            swapped_bytes[8..16].reverse();
-            
-            AttributeValue::UUID(Uuid::from_bytes(swapped_bytes))
+            AttributeValue::UUID(guid_from_lsf(uuid_bytes))
         },
 
-        // TranslatedString - complex structure with version-dependent parsing
+        // TranslatedString - version (BG3) or inline value (older), then handle
         AttributeType::TranslatedString => {
-            if length < 4 {
-                // Fallback for malformed data
-                return Ok(AttributeValue::TranslatedString { 
-                    value: String::new(), 
-                    handle: String::new() 
-                });
-            }
-
-            let mut cursor = std::io::Cursor::new({
-                let mut buffer = vec![0u8; length as usize];
-                reader.read_exact(&mut buffer)?;
-                buffer
-            });
-
-            // LSLib checks LSF version to decide parsing strategy
-            // For now, use a simplified approach that handles the common case
-            let version = cursor.read_u16::<LittleEndian>().unwrap_or(0);
-            let value_len = cursor.read_u16::<LittleEndian>().unwrap_or(0);
-            
-            let mut value = String::new();
-            let mut handle = String::new();
-            
-            if value_len > 0 && u32::from(value_len) < length {
-                let mut value_bytes = vec![0u8; value_len as usize];
-                cursor.read_exact(&mut value_bytes).unwrap_or_default();
-                value = String::from_utf8_lossy(&value_bytes).to_string();
-            }
-            
-            // Try to read handle if remaining data
-            let remaining = (length as usize).saturating_sub(cursor.position() as usize);
-            if remaining > 0 {
-                let mut handle_bytes = vec![0u8; remaining];
-                cursor.read_exact(&mut handle_bytes).unwrap_or_default();
-                handle = String::from_utf8_lossy(&handle_bytes).trim_end_matches('\0').to_string();
-            }
-
-            AttributeValue::TranslatedString { value, handle }
+            let (version, value) = if format.translated_string_versioned {
+                (reader.read_u16::<LittleEndian>()?, None)
+            } else {
+                (0, Some(read_length_prefixed_string(reader)?))
+            };
+            let handle = read_length_prefixed_string(reader)?;
+            AttributeValue::TranslatedString(TranslatedString { version, value, handle })
         },
 
         // TranslatedFSString - TranslatedString with recursive argument list
         AttributeType::TranslatedFSString => {
-            if length < 4 {
-                return Ok(AttributeValue::TranslatedFSString { 
-                    value: String::new(), 
-                    handle: String::new() 
-                });
-            }
-
-            let mut cursor = std::io::Cursor::new({
-                let mut buffer = vec![0u8; length as usize];
-                reader.read_exact(&mut buffer)?;
-                buffer
-            });
-
-            // Simplified parsing - in practice this would recursively parse arguments
-            let version = cursor.read_u16::<LittleEndian>().unwrap_or(0);
-            let value_len = cursor.read_u16::<LittleEndian>().unwrap_or(0);
-            
-            let mut value = String::new();
-            let mut handle = String::new();
-            
-            if value_len > 0 && u32::from(value_len) < length {
-                let mut value_bytes = vec![0u8; value_len as usize];
-                cursor.read_exact(&mut value_bytes).unwrap_or_default();
-                value = String::from_utf8_lossy(&value_bytes).to_string();
-            }
-            
-            let remaining = (length as usize).saturating_sub(cursor.position() as usize);
-            if remaining > 0 {
-                let mut handle_bytes = vec![0u8; remaining];
-                cursor.read_exact(&mut handle_bytes).unwrap_or_default();
-                handle = String::from_utf8_lossy(&handle_bytes).trim_end_matches('\0').to_string();
-            }
-
-            AttributeValue::TranslatedFSString { value, handle }
+            AttributeValue::TranslatedFSString(read_translated_fs_string(reader, format, 0)?)
         },
 
         // ScratchBuffer - raw byte data
@@ -1004,240 +1090,488 @@ fn read_attribute_value<R: Read>(reader: &mut R, attr_type: &AttributeType, leng
     })
 }
 
-fn collect_children(nodes: &mut [Option<Node>], parent_idx: usize, node_entries: &[NodeEntry]) -> Vec<Node> {
-    let mut children = Vec::new();
+/// Detach the children of `parent_idx` (and, recursively, their own children) from `nodes`.
+fn collect_children(nodes: &mut [Option<Node>], parent_idx: usize, parent_id: &str, child_indices: &[Vec<usize>]) -> Vec<Node> {
+    let mut children = Vec::with_capacity(child_indices[parent_idx].len());
 
-    if let Some(parent_node) = nodes[parent_idx].take() {
-        children.push(parent_node);
-
-        // Find children of this node
-        for (child_idx, node_entry) in node_entries.iter().enumerate() {
-            if node_entry.parent_index == parent_idx as i32 {
-                let child_nodes = collect_children(nodes, child_idx, node_entries);
-                children.extend(child_nodes);
-            }
+    for &child_idx in &child_indices[parent_idx] {
+        if let Some(mut child) = nodes[child_idx].take() {
+            child.parent = Some(parent_id.to_string());
+            child.children = collect_children(nodes, child_idx, &child.id, child_indices);
+            children.push(child);
         }
     }
 
     children
 }
 
-pub fn write_lsf<P: AsRef<Path>>(resource: &Resource, path: P) -> Result<()> {
-    use std::io::BufWriter;
+/// Settings for `write_lsf`.
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// Format version to produce, e.g. `ExtendedNodes` for DOS2 or `Bg3Patch3` for BG3
+    pub version: LsfVersion,
+    pub compression: CompressionMethod,
+    pub level: CompressionLevel,
+}
+
+impl Default for WriteOptions {
+    /// The latest BG3 version, LZ4 at the default level, as shipped BG3 mods use.
+    fn default() -> Self {
+        Self {
+            version: LsfVersion::Bg3Patch3,
+            compression: CompressionMethod::Lz4,
+            level: CompressionLevel::Default,
+        }
+    }
+}
 
-    let file = File::create(path)?;
-    let mut writer = BufWriter::new(file);
+impl WriteOptions {
+    /// The version and compression `resource` was read with, or the defaults if it did not come from an LSF file.
+    pub fn for_resource(resource: &Resource) -> Self {
+        match &resource.lsf_layout {
+            Some(layout) => Self {
+                version: layout.version,
+                compression: layout.compression,
+                level: layout.compression_level,
+            },
+            None => Self::default(),
+        }
+    }
+}
 
+/// Write `resource` as an LSF file.
+///
+/// A resource read from an LSF file keeps that file's layout; as long as its nodes and attribute
+/// names are unchanged, it is reused so an untouched file is written back byte for byte
+/// (given the same `WriteOptions`, see `WriteOptions::for_resource`).
+pub fn write_lsf<P: AsRef<Path>>(resource: &Resource, path: P, options: &WriteOptions) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    to_writer(resource, &mut writer, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serialize `resource` as an LSF file in memory; see `write_lsf`.
+pub fn to_vec(resource: &Resource, options: &WriteOptions) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    to_writer(resource, &mut data, options)?;
+    Ok(data)
+}
+
+/// Write `resource` as an LSF file to any writer; see `write_lsf`.
+pub fn to_writer<W: Write>(resource: &Resource, mut writer: W, options: &WriteOptions) -> Result<()> {
     // Write LSF header
     writer.write_all(LSF_MAGIC)?;
-    writer.write_u32::<LittleEndian>(resource.metadata.major_version)?;
-    writer.write_u64::<LittleEndian>(0)?; // engine_version placeholder
+    let version = options.version;
+    writer.write_u32::<LittleEndian>(version as u32)?;
+    let engine_version = pack_engine_version(&resource.metadata, version)?;
+    if version >= LsfVersion::Bg3ExtendedHeader {
+        writer.write_u64::<LittleEndian>(engine_version)?;
+    } else {
+        writer.write_u32::<LittleEndian>(engine_version as u32)?;
+    }
 
-    // For now, create a minimal LSF file with empty chunks
-    // This is a basic implementation to enable round-trip testing
+    // Node and attribute entries use the V3 layouts since v3, unless the resource comes
+    // from a file of this version stored without them
+    let metadata_format = match &resource.lsf_layout {
+        Some(layout) if layout.version == version => layout.metadata_format,
+        _ if version >= LsfVersion::ExtendedNodes => LsfMetadataFormat::KeysAndAdjacency,
+        _ => LsfMetadataFormat::None,
+    };
+    let extended = metadata_format.has_adjacency(version);
+
+    let roots: Vec<&Node> = resource.regions.values().flat_map(|region| &region.nodes).collect();
+    let layout = resource.lsf_layout.as_ref().filter(|layout| layout_matches(layout, &roots));
+    let mut entries = ResourceEntries::new(layout, extended);
+    let format = ValueFormat::new(version, &resource.metadata);
+    for root in roots {
+        entries.add_node(root, -1, format)?;
+    }
+    if let Some(layout) = layout.filter(|layout| layout.metadata_format.has_adjacency(layout.version)) {
+        for (entry, recorded) in entries.nodes.iter_mut().zip(&layout.nodes) {
+            entry.next_sibling_index = recorded.next_sibling_index;
+        }
+    }
+    if let Some(key) = entries.keys.first() {
+        if version < LsfVersion::Bg3NodeKeys {
+            bail!("Node {} has a key, but LSF v{} cannot store node keys (v6 or later required)",
+                key.node_index, version as u32);
+        }
+        if metadata_format != LsfMetadataFormat::KeysAndAdjacency {
+            bail!("Node {} has a key, but metadata format {:?} cannot store node keys", key.node_index, metadata_format);
+        }
+    }
 
-    // Create minimal metadata for LSFMetadataV6
-    let strings_data = create_strings_chunk(resource)?;
-    let keys_data = Vec::new(); // Empty keys chunk
-    let nodes_data = create_nodes_chunk(resource)?;
-    let attributes_data = create_attributes_chunk(resource)?;
-    let values_data = create_values_chunk(resource)?;
+    // Chunks in file order: Strings, Keys, Nodes, Attributes, Values
+    let chunks = [
+        entries.strings.to_bytes()?,
+        write_key_entries(&entries.keys)?,
+        write_node_entries(&entries.nodes, extended)?,
+        write_attribute_entries(&entries.nodes, &entries.attributes, extended)?,
+        entries.values,
+    ];
+    let chunked = version >= LsfVersion::ChunkedCompress;
+    let mut compressed_chunks = Vec::with_capacity(chunks.len());
+    for (chunk_idx, data) in chunks.iter().enumerate() {
+        // The strings chunk is never chunked (see `read_chunks`)
+        compressed_chunks.push(compress(data, options.compression, options.level, chunked && chunk_idx > 0)?);
+    }
 
-    // Compress chunks if needed (for now, store uncompressed)
-    let compression_method = CompressionMethod::None;
+    // Before v6 there is no keys chunk
+    let has_keys = version >= LsfVersion::Bg3NodeKeys;
+    let mut chunks: Vec<_> = chunks.into_iter().zip(compressed_chunks).collect();
+    if !has_keys {
+        chunks.remove(1);
+    }
 
-    // Write LSFMetadataV6
-    write_metadata_v6(&mut writer, &strings_data, &keys_data, &nodes_data, &attributes_data, &values_data, compression_method)?;
+    let compression_flags = make_compression_flags(options.compression, options.level);
+    let sizes_on_disk = compression_flags != 0 || resource.lsf_layout.as_ref().is_some_and(|layout| layout.sizes_on_disk);
+    let unknown = resource.lsf_layout.as_ref().map_or((0, 0), |layout| (layout.unknown2, layout.unknown3));
+    write_metadata(&mut writer, &chunks, compression_flags, sizes_on_disk, unknown, metadata_format)?;
 
     // Write chunk data
-    writer.write_all(&strings_data)?;
-    writer.write_all(&keys_data)?;
-    writer.write_all(&nodes_data)?;
-    writer.write_all(&attributes_data)?;
-    writer.write_all(&values_data)?;
+    for (_, data) in &chunks {
+        writer.write_all(data)?;
+    }
 
     Ok(())
 }
 
-fn write_metadata_v6<W: Write>(
-    writer: &mut W,
-    strings_data: &[u8],
-    keys_data: &[u8],
-    nodes_data: &[u8],
-    attributes_data: &[u8],
-    values_data: &[u8],
-    _compression_method: CompressionMethod,
-) -> Result<()> {
-    // Write LSFMetadataV6 structure
-    writer.write_u32::<LittleEndian>(strings_data.len() as u32)?;  // strings_uncompressed_size
-    writer.write_u32::<LittleEndian>(0)?;                         // strings_compressed_size (0 = uncompressed)
-    writer.write_u32::<LittleEndian>(keys_data.len() as u32)?;    // keys_uncompressed_size
-    writer.write_u32::<LittleEndian>(0)?;                         // keys_compressed_size
-    writer.write_u32::<LittleEndian>(nodes_data.len() as u32)?;   // nodes_uncompressed_size
-    writer.write_u32::<LittleEndian>(0)?;                         // nodes_compressed_size
-    writer.write_u32::<LittleEndian>(attributes_data.len() as u32)?; // attributes_uncompressed_size
-    writer.write_u32::<LittleEndian>(0)?;                         // attributes_compressed_size
-    writer.write_u32::<LittleEndian>(values_data.len() as u32)?;  // values_uncompressed_size
-    writer.write_u32::<LittleEndian>(0)?;                         // values_compressed_size
-    writer.write_u32::<LittleEndian>(0)?;                         // compression_flags
-    writer.write_u32::<LittleEndian>(0)?;                         // unknown2
-    writer.write_u32::<LittleEndian>(0)?;                         // unknown3
-    writer.write_u32::<LittleEndian>(0)?;                         // unknown4
+/// Whether `roots` flatten to exactly the node entries recorded in `layout`, with the same attribute names in the same order.
+fn layout_matches(layout: &LsfLayout, roots: &[&Node]) -> bool {
+    fn visit(node: &Node, parent_index: i32, recorded: &[LsfNodeLayout], next_index: &mut usize) -> bool {
+        let index = *next_index;
+        let Some(entry) = recorded.get(index) else {
+            return false;
+        };
+        *next_index += 1;
 
-    Ok(())
+        entry.name == node.id
+            && entry.parent_index == parent_index
+            && entry.attributes.len() == node.attributes.len()
+            && entry.attributes.iter().zip(node.attributes.keys()).all(|(attr, name)| &attr.name == name)
+            && node.children.iter().all(|child| visit(child, index as i32, recorded, next_index))
+    }
+
+    let mut next_index = 0;
+    roots.iter().all(|root| visit(root, -1, &layout.nodes, &mut next_index)) && next_index == layout.nodes.len()
 }
 
-fn create_strings_chunk(resource: &Resource) -> Result<Vec<u8>> {
-    let mut data = Vec::new();
+/// Outcome of `verify_roundtrip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoundTrip {
+    /// The rewritten file is byte-identical to the original
+    Identical,
+    /// Header and decompressed chunks are identical, but our compressor produced different bytes
+    SameChunks,
+}
 
-    // Create empty hash table header (0 buckets)
-    data.write_u32::<LittleEndian>(0)?;
+/// Read an LSF file and write it back with its recorded layout and compression settings,
+/// failing with the first difference if the result is not the same file.
+pub fn verify_roundtrip<P: AsRef<Path>>(path: P) -> Result<RoundTrip> {
+    let original = std::fs::read(path)?;
+    let (resource, _) = from_bytes(&original, &ReadOptions { strict: true })?;
+    let rewritten = to_vec(&resource, &WriteOptions::for_resource(&resource))?;
+    if rewritten == original {
+        return Ok(RoundTrip::Identical);
+    }
 
-    // Collect all strings from the resource
-    let mut strings = Vec::new();
+    let before = read_chunks(&mut Cursor::new(&original))?;
+    let after = read_chunks(&mut Cursor::new(&rewritten))?;
+    if before.header.engine_version != after.header.engine_version {
+        bail!("Engine version differs: 0x{:x} was rewritten as 0x{:x}",
+            before.header.engine_version, after.header.engine_version);
+    }
+    let fields = [
+        ("compression flags", u32::from(before.metadata.compression_flags), u32::from(after.metadata.compression_flags)),
+        ("metadata byte 2", u32::from(before.metadata.unknown2), u32::from(after.metadata.unknown2)),
+        ("metadata bytes 3-4", u32::from(before.metadata.unknown3), u32::from(after.metadata.unknown3)),
+        ("metadata format", before.metadata.metadata_format as u32, after.metadata.metadata_format as u32),
+    ];
+    for (field, original_value, rewritten_value) in fields {
+        if original_value != rewritten_value {
+            bail!("{} differ: {} was rewritten as {}", field, original_value, rewritten_value);
+        }
+    }
 
-    // Add region names
-    for region_name in resource.regions.keys() {
-        strings.push(region_name.clone());
+    let chunks = [
+        ("strings", &before.strings_data, &after.strings_data),
+        ("keys", &before.keys_data, &after.keys_data),
+        ("nodes", &before.nodes_data, &after.nodes_data),
+        ("attributes", &before.attributes_data, &after.attributes_data),
+        ("values", &before.values_data, &after.values_data),
+    ];
+    for (chunk, original_data, rewritten_data) in chunks {
+        if let Some(offset) = original_data.iter().zip(rewritten_data.iter()).position(|(a, b)| a != b) {
+            bail!("{} chunk differs at offset 0x{:x}", chunk, offset);
+        }
+        if original_data.len() != rewritten_data.len() {
+            bail!("{} chunk is {} bytes, but was rewritten as {} bytes", chunk, original_data.len(), rewritten_data.len());
+        }
     }
 
-    // Add node names and attribute names/values
-    for region in resource.regions.values() {
-        for node in &region.nodes {
-            if let Some(name) = &node.name {
-                if !strings.contains(name) {
-                    strings.push(name.clone());
-                }
-            }
+    if before.metadata.compression_flags == 0 {
+        bail!("Chunks are identical, but the rewritten file is not");
+    }
+    Ok(RoundTrip::SameChunks)
+}
 
-            for (attr_name, attr) in &node.attributes {
-                if !strings.contains(attr_name) {
-                    strings.push(attr_name.clone());
-                }
+/// Write LSFMetadataV5/V6 (which one depends on whether `chunks` includes the keys):
+/// per `(uncompressed, compressed)` chunk the uncompressed size and the size on disk,
+/// which is left 0 unless `sizes_on_disk`.
+fn write_metadata<W: Write>(
+    writer: &mut W,
+    chunks: &[(Vec<u8>, Vec<u8>)],
+    compression_flags: u8,
+    sizes_on_disk: bool,
+    (unknown2, unknown3): (u8, u16),
+    metadata_format: LsfMetadataFormat,
+) -> Result<()> {
+    for (data, compressed_data) in chunks {
+        writer.write_u32::<LittleEndian>(data.len() as u32)?;
+        writer.write_u32::<LittleEndian>(if sizes_on_disk { compressed_data.len() as u32 } else { 0 })?;
+    }
+    writer.write_u8(compression_flags)?;
+    writer.write_u8(unknown2)?;
+    writer.write_u16::<LittleEndian>(unknown3)?;
+    writer.write_u32::<LittleEndian>(metadata_format as u32)?;    // metadata_format
 
-                // Add string values
-                match &attr.value {
-                    crate::resource::AttributeValue::String(s) |
-                    crate::resource::AttributeValue::Path(s) |
-                    crate::resource::AttributeValue::FixedString(s) |
-                    crate::resource::AttributeValue::LSString(s) => {
-                        if !strings.contains(s) {
-                            strings.push(s.clone());
-                        }
-                    },
-                    _ => {}
-                }
+    Ok(())
+}
+
+/// Number of buckets LSLib allocates for the strings chunk it writes.
+const STRING_TABLE_BUCKETS: usize = 0x200;
+
+impl StringTable {
+    fn for_writing() -> Self {
+        Self::from_buckets(vec![Vec::new(); STRING_TABLE_BUCKETS])
+    }
+
+    /// Continue a table read from a file, keeping its names where they were.
+    fn from_buckets(buckets: Vec<Vec<String>>) -> Self {
+        let mut handles = HashMap::new();
+        for (bucket_idx, chain) in buckets.iter().enumerate() {
+            for (chain_idx, string) in chain.iter().enumerate() {
+                handles.entry(string.clone()).or_insert(((bucket_idx as u32) << 16) | chain_idx as u32);
             }
         }
+        StringTable { buckets, handles }
     }
 
-    // Add hardcoded strings that should be present based on the test
-    if !strings.contains(&"ActiveProfile".to_string()) {
-        strings.push("ActiveProfile".to_string());
-    }
-    if !strings.contains(&"UserProfiles".to_string()) {
-        strings.push("UserProfiles".to_string());
-    }
+    /// Return the handle of `string`, adding it to its bucket's chain if needed.
+    fn add(&mut self, string: &str) -> Result<u32> {
+        if let Some(&handle) = self.handles.get(string) {
+            return Ok(handle);
+        }
+        if string.len() > u16::MAX as usize {
+            bail!("Name '{}...' is longer than {} bytes", string.chars().take(32).collect::<String>(), u16::MAX);
+        }
 
-    // Write strings in sequential format (after empty hash table)
-    // Add padding to simulate the offset structure seen in the original
-    data.resize(712, 0); // Pad to match the offset where "ActiveProfile" was found
+        // LSLib folds the 32-bit string hash into 9 bits; tables read from files may have fewer buckets
+        let hash = lslib_string_hash(string);
+        let folded = (hash & 0x1ff) ^ ((hash >> 9) & 0x1ff) ^ ((hash >> 18) & 0x1ff) ^ ((hash >> 27) & 0x1ff);
+        let bucket_idx = folded as usize % self.buckets.len();
+        let chain = &mut self.buckets[bucket_idx];
+        if chain.len() > u16::MAX as usize {
+            bail!("String table bucket {} is full", bucket_idx);
+        }
+        chain.push(string.to_string());
 
-    for string in &strings {
-        // Write string with length prefix (pattern from original: 01 00 0D 00 for "ActiveProfile")
-        data.write_u8(1)?; // flag?
-        data.write_u8(0)?; // padding
-        data.write_u16::<LittleEndian>(string.len() as u16)?; // length
-        data.extend_from_slice(string.as_bytes());
+        let handle = ((bucket_idx as u32) << 16) | (chain.len() - 1) as u32;
+        self.handles.insert(string.to_string(), handle);
+        Ok(handle)
     }
 
-    Ok(data)
+    /// Serialize in the layout read by `parse_string_table`.
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        data.write_u32::<LittleEndian>(self.buckets.len() as u32)?;
+        for chain in &self.buckets {
+            data.write_u16::<LittleEndian>(chain.len() as u16)?;
+            for string in chain {
+                data.write_u16::<LittleEndian>(string.len() as u16)?;
+                data.write_all(string.as_bytes())?;
+            }
+        }
+        Ok(data)
+    }
 }
 
-fn create_nodes_chunk(resource: &Resource) -> Result<Vec<u8>> {
-    let mut data = Vec::new();
-
-    // If no regions exist, create at least one minimal node to match original structure
-    if resource.regions.is_empty() {
-        // Write a single LSFNodeEntryV3 structure
-        data.write_u32::<LittleEndian>(0xffffffff)?; // name_hash_table_index (match original pattern)
-        data.write_i32::<LittleEndian>(0)?;          // parent_index (0 for root)
-        data.write_i32::<LittleEndian>(0x01670000)?; // next_sibling_index (match original pattern)
-        data.write_i32::<LittleEndian>(0x00095600)?; // first_attribute_index (match original pattern)
-        return Ok(data);
+/// `String.GetHashCode()` of the 64-bit .NET Framework, which LSLib buckets names with.
+/// Newer .NET runtimes randomize this hash, so readers never recompute it; using the
+/// deterministic variant keeps our output stable.
+fn lslib_string_hash(string: &str) -> u32 {
+    let mut hash1: i32 = 5381;
+    let mut hash2: i32 = 5381;
+    // The original walks a null-terminated UTF-16 buffer two code units at a time
+    let units: Vec<u16> = string.encode_utf16().take_while(|&c| c != 0).collect();
+    for pair in units.chunks(2) {
+        hash1 = (hash1 << 5).wrapping_add(hash1) ^ i32::from(pair[0]);
+        if let Some(&c) = pair.get(1) {
+            hash2 = (hash2 << 5).wrapping_add(hash2) ^ i32::from(c);
+        }
     }
+    hash1.wrapping_add(hash2.wrapping_mul(1566083941)) as u32
+}
 
-    // Write a minimal node entry for each region
-    let mut attr_index = 0;
-    for (_region_name, region) in resource.regions.iter() {
-        for node in &region.nodes {
-            // Write LSFNodeEntryV3 structure
-            data.write_u32::<LittleEndian>(0xffffffff)?; // name_hash_table_index (match original pattern)
-            data.write_i32::<LittleEndian>(0)?;          // parent_index (0 for root)
-            data.write_i32::<LittleEndian>(0x01670000)?; // next_sibling_index (match original pattern)
+/// The resource tree flattened into LSF entries, in the order LSLib writes them:
+/// every node is followed by its attributes and then by its children, depth first.
+///
+/// With a `layout` that matches the tree (see `layout_matches`), its string table and value
+/// placement are reproduced.
+struct ResourceEntries<'a> {
+    strings: StringTable,
+    nodes: Vec<NodeEntry>,
+    attributes: Vec<AttributeEntry>,
+    keys: Vec<KeyEntry>,
+    values: Vec<u8>,
+    layout: Option<&'a LsfLayout>,
+    /// Value offsets are stored, so gaps between values survive (V3 attribute entries)
+    explicit_offsets: bool,
+}
 
-            // Set first_attribute_index if node has attributes
-            if !node.attributes.is_empty() {
-                data.write_i32::<LittleEndian>(attr_index)?;
-                attr_index += 1;
-            } else {
-                data.write_i32::<LittleEndian>(0x00095600)?; // match original pattern
-            }
+impl<'a> ResourceEntries<'a> {
+    fn new(layout: Option<&'a LsfLayout>, explicit_offsets: bool) -> Self {
+        let strings = match layout {
+            Some(layout) if !layout.string_buckets.is_empty() => StringTable::from_buckets(layout.string_buckets.clone()),
+            _ => StringTable::for_writing(),
+        };
+        Self {
+            strings,
+            nodes: Vec::new(),
+            attributes: Vec::new(),
+            keys: Vec::new(),
+            values: Vec::new(),
+            layout,
+            explicit_offsets,
         }
     }
 
-    Ok(data)
-}
+    /// Append `node` and its subtree, returning the index of its entry.
+    fn add_node(&mut self, node: &Node, parent_index: i32, format: ValueFormat) -> Result<i32> {
+        let node_index = self.nodes.len() as i32;
+        let node_name = node.id.as_str();
+        self.nodes.push(NodeEntry {
+            name_hash_table_index: self.strings.add(node_name)?,
+            parent_index,
+            next_sibling_index: -1,
+            first_attribute_index: -1,
+        });
+
+        if let Some(key) = &node.key {
+            self.keys.push(KeyEntry {
+                node_index: node_index as u32,
+                key_name_hash_table_index: self.strings.add(key)?,
+            });
+        }
 
-fn create_attributes_chunk(resource: &Resource) -> Result<Vec<u8>> {
-    let mut data = Vec::new();
+        let recorded = self.layout.map(|layout| layout.nodes[node_index as usize].attributes.as_slice());
+        let mut previous_attribute: Option<usize> = None;
+        for (position, (attr_name, attr)) in node.attributes.iter().enumerate() {
+            let attr_index = self.attributes.len();
+            let attr_layout = recorded.map(|recorded| &recorded[position]);
+            if let Some(attr_layout) = attr_layout.filter(|_| self.explicit_offsets) {
+                if attr_layout.offset as usize > self.values.len() {
+                    self.values.resize(attr_layout.offset as usize, 0);
+                }
+            }
+            let offset = self.values.len();
+            write_attribute_value(&mut self.values, &attr.value, format)?;
+            if let Some(attr_layout) = attr_layout.filter(|_| is_null_terminated(attr.attribute_type)) {
+                // Restore the null padding the reader dropped
+                if offset + (attr_layout.length as usize) > self.values.len() {
+                    self.values.resize(offset + attr_layout.length as usize, 0);
+                }
+            }
+            let length = self.values.len() - offset;
+            if length >= 1 << 26 {
+                bail!("Value of attribute '{}' on node '{}' is {} bytes; LSF allows at most {}",
+                    attr_name, node_name, length, (1 << 26) - 1);
+            }
 
-    // Write attribute entries for all attributes in all nodes
-    for region in resource.regions.values() {
-        for node in &region.nodes {
-            for (attr_name, attr) in &node.attributes {
-                // Write LSFAttributeEntryV3 structure (from original pattern)
-                data.write_u32::<LittleEndian>(0xffffffff)?; // name_hash_table_index
-                data.write_u32::<LittleEndian>(0)?;          // type_and_length (placeholder)
-                data.write_u32::<LittleEndian>(0x31303532)?; // next_attribute_index (pattern from original)
-                data.write_u32::<LittleEndian>(0x39303637)?; // offset (pattern from original)
+            self.attributes.push(AttributeEntry {
+                name_hash_table_index: self.strings.add(attr_name)?,
+                type_and_length: attr.attribute_type as u32 | (length as u32) << 6,
+                next_attribute_index: -1,
+                offset: offset as u32,
+            });
+            match previous_attribute {
+                Some(previous) => self.attributes[previous].next_attribute_index = attr_index as i32,
+                None => self.nodes[node_index as usize].first_attribute_index = attr_index as i32,
             }
+            previous_attribute = Some(attr_index);
         }
-    }
 
-    // If no attributes found, create a minimal entry to match original structure
-    if data.is_empty() {
-        data.write_u32::<LittleEndian>(0xffffffff)?; // name_hash_table_index
-        data.write_u32::<LittleEndian>(0)?;          // type_and_length
-        data.write_u32::<LittleEndian>(0x31303532)?; // next_attribute_index ("2051" in ASCII)
-        data.write_u32::<LittleEndian>(0x39303637)?; // offset ("7609" in ASCII)
+        let mut previous_child: Option<i32> = None;
+        for child in &node.children {
+            let child_index = self.add_node(child, node_index, format)?;
+            if let Some(previous) = previous_child {
+                self.nodes[previous as usize].next_sibling_index = child_index;
+            }
+            previous_child = Some(child_index);
+        }
+
+        Ok(node_index)
     }
+}
 
+/// Serialize node entries as LSFNodeEntryV3, or LSFNodeEntryV2 when not `extended`.
+fn write_node_entries(entries: &[NodeEntry], extended: bool) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(entries.len() * 16);
+    for entry in entries {
+        data.write_u32::<LittleEndian>(entry.name_hash_table_index)?;
+        if extended {
+            data.write_i32::<LittleEndian>(entry.parent_index)?;
+            data.write_i32::<LittleEndian>(entry.next_sibling_index)?;
+            data.write_i32::<LittleEndian>(entry.first_attribute_index)?;
+        } else {
+            data.write_i32::<LittleEndian>(entry.first_attribute_index)?;
+            data.write_i32::<LittleEndian>(entry.parent_index)?;
+        }
+    }
     Ok(data)
 }
 
-fn create_values_chunk(resource: &Resource) -> Result<Vec<u8>> {
-    let mut data = Vec::new();
-
-    // Write attribute values for all attributes in all nodes
-    for region in resource.regions.values() {
-        for node in &region.nodes {
-            for (_attr_name, attr) in &node.attributes {
-                // Write the attribute value based on its type
-                write_attribute_value(&mut data, &attr.value)?;
-            }
+/// Serialize attribute entries as LSFAttributeEntryV3, or LSFAttributeEntryV2 when not `extended`.
+///
+/// V2 entries name their node instead of the next attribute and have no value offset; readers
+/// rebuild both from entry order, which `ResourceEntries` keeps grouped by node.
+fn write_attribute_entries(nodes: &[NodeEntry], entries: &[AttributeEntry], extended: bool) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(entries.len() * 16);
+    if extended {
+        for entry in entries {
+            data.write_u32::<LittleEndian>(entry.name_hash_table_index)?;
+            data.write_u32::<LittleEndian>(entry.type_and_length)?;
+            data.write_i32::<LittleEndian>(entry.next_attribute_index)?;
+            data.write_u32::<LittleEndian>(entry.offset)?;
         }
+        return Ok(data);
     }
 
-    // If no values, create a minimal values chunk to match original size (37 bytes -> 29 actual)
-    if data.is_empty() {
-        data.resize(29, 0);
+    for (node_idx, node) in nodes.iter().enumerate() {
+        let mut attr_index = node.first_attribute_index;
+        while attr_index >= 0 {
+            let entry = &entries[attr_index as usize];
+            data.write_u32::<LittleEndian>(entry.name_hash_table_index)?;
+            data.write_u32::<LittleEndian>(entry.type_and_length)?;
+            data.write_i32::<LittleEndian>(node_idx as i32)?;
+            attr_index = entry.next_attribute_index;
+        }
     }
+    Ok(data)
+}
 
+fn write_key_entries(entries: &[KeyEntry]) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(entries.len() * 8);
+    for entry in entries {
+        data.write_u32::<LittleEndian>(entry.node_index)?;
+        data.write_u32::<LittleEndian>(entry.key_name_hash_table_index)?;
+    }
     Ok(data)
 }
 
-fn write_attribute_value<W: Write>(writer: &mut W, value: &crate::resource::AttributeValue) -> Result<()> {
+/// Inverse of `read_attribute_value`; the bytes written are the value's length.
+fn write_attribute_value<W: Write>(writer: &mut W, value: &crate::resource::AttributeValue, format: ValueFormat) -> Result<()> {
     match value {
         crate::resource::AttributeValue::None => {},
         crate::resource::AttributeValue::Byte(v) => writer.write_u8(*v)?,
@@ -1251,7 +1585,9 @@ fn write_attribute_value<W: Write>(writer: &mut W, value: &crate::resource::Attr
         crate::resource::AttributeValue::String(s) |
         crate::resource::AttributeValue::Path(s) |
         crate::resource::AttributeValue::FixedString(s) |
-        crate::resource::AttributeValue::LSString(s) => {
+        crate::resource::AttributeValue::LSString(s) |
+        crate::resource::AttributeValue::WString(s) |
+        crate::resource::AttributeValue::LSWString(s) => {
             writer.write_all(s.as_bytes())?;
             writer.write_u8(0)?; // null terminator
         },
@@ -1259,7 +1595,7 @@ fn write_attribute_value<W: Write>(writer: &mut W, value: &crate::resource::Attr
         crate::resource::AttributeValue::LongLong(v) => writer.write_i64::<LittleEndian>(*v)?,
         crate::resource::AttributeValue::Int8(v) => writer.write_i8(*v)?,
         crate::resource::AttributeValue::Int64(v) => writer.write_i64::<LittleEndian>(*v)?,
-        crate::resource::AttributeValue::UUID(uuid) => writer.write_all(uuid.as_bytes())?,
+        crate::resource::AttributeValue::UUID(uuid) => writer.write_all(&guid_to_lsf(uuid))?,
         crate::resource::AttributeValue::IVec2(vec) => {
             for v in vec {
                 writer.write_i32::<LittleEndian>(*v)?;
@@ -1315,20 +1651,16 @@ fn write_attribute_value<W: Write>(writer: &mut W, value: &crate::resource::Attr
                 writer.write_f32::<LittleEndian>(*v)?;
             }
         },
-        crate::resource::AttributeValue::TranslatedString { value, .. } => {
-            writer.write_all(value.as_bytes())?;
-            writer.write_u8(0)?; // null terminator
-        },
-        crate::resource::AttributeValue::TranslatedFSString { value, .. } => {
-            writer.write_all(value.as_bytes())?;
-            writer.write_u8(0)?; // null terminator
-        },
-        crate::resource::AttributeValue::WString(s) |
-        crate::resource::AttributeValue::LSWString(s) => {
-            for ch in s.encode_utf16() {
-                writer.write_u16::<LittleEndian>(ch)?;
+        crate::resource::AttributeValue::TranslatedString(ts) => {
+            if format.translated_string_versioned {
+                writer.write_u16::<LittleEndian>(ts.version)?;
+            } else {
+                write_length_prefixed_string(writer, ts.value.as_deref().unwrap_or_default())?;
             }
-            writer.write_u16::<LittleEndian>(0)?; // null terminator
+            write_length_prefixed_string(writer, &ts.handle)?;
+        },
+        crate::resource::AttributeValue::TranslatedFSString(fs) => {
+            write_translated_fs_string(writer, fs, format)?;
         },
         crate::resource::AttributeValue::ScratchBuffer(buffer) => {
             writer.write_all(buffer)?;
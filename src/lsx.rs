@@ -1,249 +1,883 @@
-use anyhow::Result;
-use quick_xml::{Reader, Writer, events::{Event, BytesEnd, BytesStart, BytesText}};
-use std::collections::HashMap;
+use anyhow::{Result, bail};
+use quick_xml::{Reader, events::{Event, BytesStart}};
+use indexmap::IndexMap;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Write};
+use std::io::{BufRead, BufWriter, Write};
 use std::path::Path;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::diagnostics::{Diagnostic, ReadOptions, ReadReport};
+use crate::lsf::{LsfMetadataFormat, MAX_FS_STRING_DEPTH, swap_guid_byte_pairs};
+use crate::resource::{Resource, Metadata, Region, Node, NodeAttribute, AttributeType, AttributeValue, TranslatedString, TranslatedFSString, TranslatedFSStringArgument};
+
+/// Indentation of each nesting level in a written LSX document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indent {
+    /// Everything on a single line
+    None,
+    Spaces(usize),
+    Tabs,
+}
+
+/// How float, vector and matrix components are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// Rust's `to_string`: shortest round-trip digits, never in exponent notation
+    Rust,
+    /// .NET's `ToString()` as used by LSLib: the same digits, with exponent notation (`1E-05`,
+    /// `1E+09`) below 0.0001 and beyond 9 (float) or 15 (double) integer digits
+    DotNet,
+}
+
+/// What to write for a node without children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmptyChildren {
+    /// No `<children>` element, as LSLib does
+    Omit,
+    /// An empty `<children />` element
+    SelfClosing,
+}
+
+/// The `lslib_meta` attribute of the `<version>` element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LslibMeta {
+    Omit,
+    /// What LSLib writes for the resource: GUIDs are byte-swapped, plus the LSF metadata
+    /// format it was read with (BG3's keys and adjacency when it did not come from LSF)
+    Derive,
+    Value(String),
+}
+
+/// Which game's spelling of attribute types to write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LsxDialect {
+    /// Numeric type ids (`type="22"`), as DOS2 and older games expect
+    Dos2,
+    /// LSLib's type names (`type="FixedString"`)
+    Bg3,
+}
+
+impl LsxDialect {
+    /// The dialect of the game that wrote `metadata`: BG3 from `<version major="4">` on.
+    pub fn for_metadata(metadata: &Metadata) -> Self {
+        if metadata.major_version >= 4 { Self::Bg3 } else { Self::Dos2 }
+    }
+}
+
+impl FromStr for LsxDialect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "dos2" => Ok(Self::Dos2),
+            "bg3" => Ok(Self::Bg3),
+            _ => bail!("Unknown LSX dialect '{}' (expected dos2 or bg3)", s),
+        }
+    }
+}
+
+/// Layout settings for `write_lsx`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsxWriteOptions {
+    pub indent: Indent,
+    /// End lines with `\r\n` instead of `\n`
+    pub crlf: bool,
+    /// Start the document with a UTF-8 byte order mark
+    pub bom: bool,
+    pub float_format: FloatFormat,
+    pub empty_children: EmptyChildren,
+    pub lslib_meta: LslibMeta,
+    /// Close empty elements with `" />"` instead of `"/>"`
+    pub space_before_self_close: bool,
+    /// Type spelling to write; `None` follows the resource's `<version major>`
+    pub dialect: Option<LsxDialect>,
+}
+
+impl Default for LsxWriteOptions {
+    /// Two-space indentation, LF line endings and Rust float formatting.
+    fn default() -> Self {
+        Self {
+            indent: Indent::Spaces(2),
+            crlf: false,
+            bom: false,
+            float_format: FloatFormat::Rust,
+            empty_children: EmptyChildren::Omit,
+            lslib_meta: LslibMeta::Omit,
+            space_before_self_close: false,
+            dialect: None,
+        }
+    }
+}
+
+impl LsxWriteOptions {
+    /// LSLib's exact layout: a BOM, tabs, CRLF line endings, .NET float formatting,
+    /// `lslib_meta` on the version and `" />"` closing empty elements.
+    pub fn lslib() -> Self {
+        Self {
+            indent: Indent::Tabs,
+            crlf: true,
+            bom: true,
+            float_format: FloatFormat::DotNet,
+            empty_children: EmptyChildren::Omit,
+            lslib_meta: LslibMeta::Derive,
+            space_before_self_close: true,
+            dialect: None,
+        }
+    }
+}
+
+pub fn write_lsx<P: AsRef<Path>>(resource: &Resource, path: P, options: &LsxWriteOptions) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    to_writer(resource, &mut writer, options)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serialize `resource` as an LSX document in memory.
+pub fn to_vec(resource: &Resource, options: &LsxWriteOptions) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    to_writer(resource, &mut data, options)?;
+    Ok(data)
+}
+
+/// Write `resource` as an LSX document to any writer.
+pub fn to_writer<W: Write>(resource: &Resource, writer: W, options: &LsxWriteOptions) -> Result<()> {
+    let dialect = options.dialect.unwrap_or_else(|| LsxDialect::for_metadata(&resource.metadata));
+    let lslib_meta = match &options.lslib_meta {
+        LslibMeta::Omit => None,
+        LslibMeta::Derive => Some(derive_lslib_meta(resource)),
+        LslibMeta::Value(meta) => Some(meta.clone()),
+    };
+    let byte_swap_guids = swaps_guids(lslib_meta.as_deref());
+    let mut writer = LsxOutput { writer, options, dialect, byte_swap_guids, depth: 0 };
+    if options.bom {
+        writer.writer.write_all("\u{feff}".as_bytes())?;
+    }
+    writer.writer.write_all(br#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+
+    writer.start(&Element::new("save"))?;
+
+    let mut version_elem = Element::new("version");
+    version_elem.push("major", resource.metadata.major_version.to_string());
+    version_elem.push("minor", resource.metadata.minor_version.to_string());
+    version_elem.push("revision", resource.metadata.revision.to_string());
+    version_elem.push("build", resource.metadata.build_number.to_string());
+    if let Some(meta) = lslib_meta {
+        version_elem.push("lslib_meta", meta);
+    }
+    writer.empty(&version_elem)?;
 
-use crate::resource::{Resource, Metadata, Region, Node, NodeAttribute, AttributeType, AttributeValue};
-
-pub fn write_lsx<P: AsRef<Path>>(resource: &Resource, path: P) -> Result<()> {
-    let file = File::create(path)?;
-    let mut writer = Writer::new_with_indent(BufWriter::new(file), b' ', 2);
-    
-    // Write XML declaration
-    writer.write_event(Event::Decl(quick_xml::events::BytesDecl::new(
-        "1.0", Some("utf-8"), None
-    )))?;
-    
-    // Write root save element
-    let mut save_elem = BytesStart::new("save");
-    writer.write_event(Event::Start(save_elem.clone()))?;
-    
-    // Write version element
-    let mut version_elem = BytesStart::new("version");
-    version_elem.push_attribute(("major", resource.metadata.major_version.to_string().as_str()));
-    version_elem.push_attribute(("minor", resource.metadata.minor_version.to_string().as_str()));
-    version_elem.push_attribute(("revision", resource.metadata.revision.to_string().as_str()));
-    version_elem.push_attribute(("build", resource.metadata.build_number.to_string().as_str()));
-    writer.write_event(Event::Empty(version_elem))?;
-    
-    // Write regions
     for (region_name, region) in &resource.regions {
         write_region(&mut writer, region_name, region)?;
     }
-    
-    // Close save element
-    writer.write_event(Event::End(BytesEnd::new("save")))?;
-    
+
+    writer.end("save")?;
     Ok(())
 }
 
-fn write_region<W: Write>(writer: &mut Writer<W>, region_name: &str, region: &Region) -> Result<()> {
-    let mut region_elem = BytesStart::new("region");
-    region_elem.push_attribute(("id", region_name));
-    writer.write_event(Event::Start(region_elem.clone()))?;
-    
+/// The `lslib_meta` LSLib writes for `resource`.
+fn derive_lslib_meta(resource: &Resource) -> String {
+    let format = match &resource.lsf_layout {
+        Some(layout) => layout.metadata_format,
+        None => LsfMetadataFormat::KeysAndAdjacency,
+    };
+    match format {
+        LsfMetadataFormat::None => "v1,bswap_guids".to_string(),
+        LsfMetadataFormat::KeysAndAdjacency => "v1,bswap_guids,lsf_keys_adjacency".to_string(),
+        LsfMetadataFormat::None2 => "v1,bswap_guids,lsf_adjacency".to_string(),
+    }
+}
+
+/// Whether GUIDs are written byte-swapped under `lslib_meta`; LSLib assumes so when there is none.
+fn swaps_guids(lslib_meta: Option<&str>) -> bool {
+    match lslib_meta {
+        Some(meta) => meta.split(',').any(|tag| tag == "bswap_guids"),
+        None => true,
+    }
+}
+
+/// Convert between a GUID's text with and without LSLib's `ByteSwapGuids`, which swaps the pairs
+/// of its last 8 bytes.
+fn swap_guid_text_order(uuid: &Uuid) -> Uuid {
+    let mut bytes = *uuid.as_bytes();
+    swap_guid_byte_pairs(&mut bytes);
+    Uuid::from_bytes(bytes)
+}
+
+/// An element about to be written, with its attributes in order.
+struct Element {
+    name: &'static str,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl Element {
+    fn new(name: &'static str) -> Self {
+        Self { name, attributes: Vec::new() }
+    }
+
+    fn push(&mut self, key: &'static str, value: impl Into<String>) {
+        self.attributes.push((key, value.into()));
+    }
+}
+
+/// Writes elements one per line, indented and terminated as `options` asks.
+struct LsxOutput<'o, W: Write> {
+    writer: W,
+    options: &'o LsxWriteOptions,
+    dialect: LsxDialect,
+    /// `UUID` values are held byte-swapped, so they are written back unswapped when this is off
+    byte_swap_guids: bool,
+    depth: usize,
+}
+
+impl<W: Write> LsxOutput<'_, W> {
+    fn start(&mut self, element: &Element) -> Result<()> {
+        self.open_tag(element)?;
+        self.writer.write_all(b">")?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn empty(&mut self, element: &Element) -> Result<()> {
+        self.open_tag(element)?;
+        self.writer.write_all(if self.options.space_before_self_close { b" />" } else { b"/>" })?;
+        Ok(())
+    }
+
+    fn end(&mut self, name: &str) -> Result<()> {
+        self.depth -= 1;
+        self.new_line()?;
+        write!(self.writer, "</{}>", name)?;
+        Ok(())
+    }
+
+    fn open_tag(&mut self, element: &Element) -> Result<()> {
+        self.new_line()?;
+        write!(self.writer, "<{}", element.name)?;
+        for (key, value) in &element.attributes {
+            write!(self.writer, " {}=\"{}\"", key, escape_attribute(value))?;
+        }
+        Ok(())
+    }
+
+    fn new_line(&mut self) -> Result<()> {
+        let indent = match self.options.indent {
+            Indent::None => return Ok(()),
+            Indent::Spaces(width) => " ".repeat(width * self.depth),
+            Indent::Tabs => "\t".repeat(self.depth),
+        };
+        self.writer.write_all(if self.options.crlf { b"\r\n" } else { b"\n" })?;
+        self.writer.write_all(indent.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Escape an attribute value the way .NET's `XmlWriter` does; line breaks and tabs become
+/// character references so that they survive attribute value normalization.
+fn escape_attribute(value: &str) -> std::borrow::Cow<'_, str> {
+    if !value.contains(['&', '<', '>', '"', '\n', '\r', '\t']) {
+        return value.into();
+    }
+    let mut escaped = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            '\t' => escaped.push_str("&#x9;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.into()
+}
+
+fn write_region<W: Write>(writer: &mut LsxOutput<W>, region_name: &str, region: &Region) -> Result<()> {
+    let mut region_elem = Element::new("region");
+    region_elem.push("id", region_name);
+    writer.start(&region_elem)?;
+
     // Write all nodes in the region
     for node in &region.nodes {
         write_node(writer, node)?;
     }
-    
-    writer.write_event(Event::End(BytesEnd::new("region")))?;
+
+    writer.end("region")?;
     Ok(())
 }
 
-fn write_node<W: Write>(writer: &mut Writer<W>, node: &Node) -> Result<()> {
-    let mut node_elem = BytesStart::new("node");
-    node_elem.push_attribute(("id", node.id.as_str()));
-    writer.write_event(Event::Start(node_elem.clone()))?;
-    
+fn write_node<W: Write>(writer: &mut LsxOutput<W>, node: &Node) -> Result<()> {
+    let mut node_elem = Element::new("node");
+    node_elem.push("id", node.id.as_str());
+    if let Some(key) = &node.key {
+        node_elem.push("key", key.as_str());
+    }
+    let omit_children = node.children.is_empty() && writer.options.empty_children == EmptyChildren::Omit;
+    if node.attributes.is_empty() && omit_children {
+        return writer.empty(&node_elem);
+    }
+    writer.start(&node_elem)?;
+
     // Write attributes
     for (attr_name, attr) in &node.attributes {
         write_attribute(writer, attr_name, attr)?;
     }
-    
+
     // Write children if any
     if !node.children.is_empty() {
-        let children_elem = BytesStart::new("children");
-        writer.write_event(Event::Start(children_elem.clone()))?;
-        
+        writer.start(&Element::new("children"))?;
         for child in &node.children {
             write_node(writer, child)?;
         }
-        
-        writer.write_event(Event::End(BytesEnd::new("children")))?;
+        writer.end("children")?;
+    } else if !omit_children {
+        writer.empty(&Element::new("children"))?;
     }
-    
-    writer.write_event(Event::End(BytesEnd::new("node")))?;
-    Ok(())
-}
 
-fn write_attribute<W: Write>(writer: &mut Writer<W>, attr_name: &str, attr: &NodeAttribute) -> Result<()> {
-    let mut attr_elem = BytesStart::new("attribute");
-    attr_elem.push_attribute(("id", attr_name));
-    attr_elem.push_attribute(("type", attr.attribute_type.as_str()));
-    attr_elem.push_attribute(("value", attr.value.to_string().as_str()));
-    writer.write_event(Event::Empty(attr_elem))?;
+    writer.end("node")?;
     Ok(())
 }
 
-pub fn read_lsx<P: AsRef<Path>>(path: P) -> Result<Resource> {
-    let file = File::open(path)?;
-    let mut reader = Reader::from_reader(BufReader::new(file));
-    reader.trim_text(true);
-    
-    let mut resource = Resource {
-        metadata: Metadata {
-            major_version: 1,
-            minor_version: 0,
-            revision: 0,
-            build_number: 0,
+fn write_attribute<W: Write>(writer: &mut LsxOutput<W>, attr_name: &str, attr: &NodeAttribute) -> Result<()> {
+    let mut attr_elem = Element::new("attribute");
+    attr_elem.push("id", attr_name);
+    match writer.dialect {
+        LsxDialect::Dos2 => attr_elem.push("type", (attr.attribute_type as u8).to_string()),
+        LsxDialect::Bg3 => attr_elem.push("type", attr.attribute_type.as_str()),
+    }
+    match &attr.value {
+        // Like LSLib: the handle, then the embedded text of older files or else the version
+        AttributeValue::TranslatedString(ts) => {
+            attr_elem.push("handle", ts.handle.as_str());
+            match &ts.value {
+                Some(value) => attr_elem.push("value", value.as_str()),
+                None => attr_elem.push("version", ts.version.to_string()),
+            }
+        },
+        AttributeValue::TranslatedFSString(fs) => {
+            push_fs_string_fields(&mut attr_elem, fs);
+            return write_fs_string_element(writer, attr_elem, &fs.arguments);
         },
-        regions: HashMap::new(),
+        AttributeValue::UUID(uuid) if !writer.byte_swap_guids => attr_elem.push("value", swap_guid_text_order(uuid).to_string()),
+        value => attr_elem.push("value", format_value(value, writer.options.float_format)),
+    }
+    writer.empty(&attr_elem)
+}
+
+/// The text of a value, with float components written in `float_format`.
+fn format_value(value: &AttributeValue, float_format: FloatFormat) -> String {
+    let join = |components: &[f32]| components.iter()
+        .map(|&component| float_format.format_f32(component))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match value {
+        AttributeValue::Float(v) => float_format.format_f32(*v),
+        AttributeValue::Double(v) => float_format.format_f64(*v),
+        AttributeValue::Vec2(v) => join(v),
+        AttributeValue::Vec3(v) => join(v),
+        AttributeValue::Vec4(v) | AttributeValue::Mat2(v) => join(v),
+        AttributeValue::Mat3(v) => join(v),
+        AttributeValue::Mat3x4(v) | AttributeValue::Mat4x3(v) => join(v),
+        AttributeValue::Mat4(v) => join(v),
+        value => value.to_string(),
+    }
+}
+
+impl FloatFormat {
+    fn format_f32(self, value: f32) -> String {
+        match self {
+            Self::Rust => value.to_string(),
+            Self::DotNet => dotnet_float(&format!("{:e}", value), 9),
+        }
+    }
+
+    fn format_f64(self, value: f64) -> String {
+        match self {
+            Self::Rust => value.to_string(),
+            Self::DotNet => dotnet_float(&format!("{:e}", value), 15),
+        }
+    }
+}
+
+/// Lay out Rust's `{:e}` output (shortest round-trip digits) like .NET's general format:
+/// positional, unless the value is below 0.0001 or has more integer digits than both
+/// `precision` and its significant digits. For the shortest round-trip string .NET takes
+/// `precision` from its default formatting precision (9 for `float`, 15 for `double`), not
+/// from the 17 digits a `double` may need, so `1e15` is written as `1E+15`.
+fn dotnet_float(scientific: &str, precision: usize) -> String {
+    let (sign, unsigned) = match scientific.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", scientific),
+    };
+    let Some((mantissa, exponent)) = unsigned.split_once('e') else {
+        // NaN and infinities
+        return match unsigned {
+            "inf" => format!("{}Infinity", sign),
+            _ => unsigned.to_string(),
+        };
     };
-    
-    let mut buf = Vec::new();
-    let mut node_stack: Vec<Node> = Vec::new();
-    let mut current_region: Option<Region> = None;
-    
-    loop {
-        match reader.read_event_into(&mut buf)? {
-            Event::Start(e) => {
-                match e.name().as_ref() {
-                    b"save" => {
-                        // Root element, continue
-                    },
-                    b"version" => {
-                        // Read version attributes
-                        for attr in e.attributes() {
-                            let attr = attr?;
-                            match attr.key.as_ref() {
-                                b"major" => resource.metadata.major_version = parse_attr_value(&attr.value)?,
-                                b"minor" => resource.metadata.minor_version = parse_attr_value(&attr.value)?,
-                                b"revision" => resource.metadata.revision = parse_attr_value(&attr.value)?,
-                                b"build" => resource.metadata.build_number = parse_attr_value(&attr.value)?,
-                                _ => {}
-                            }
-                        }
-                    },
-                    b"region" => {
-                        let mut region_id = String::new();
-                        for attr in e.attributes() {
-                            let attr = attr?;
-                            if attr.key.as_ref() == b"id" {
-                                region_id = String::from_utf8_lossy(&attr.value).to_string();
-                            }
-                        }
-                        current_region = Some(Region {
-                            name: region_id,
-                            nodes: Vec::new(),
-                        });
-                    },
-                    b"node" => {
-                        let mut node_id = String::new();
-                        for attr in e.attributes() {
-                            let attr = attr?;
-                            if attr.key.as_ref() == b"id" {
-                                node_id = String::from_utf8_lossy(&attr.value).to_string();
-                            }
-                        }
-                        let node = Node {
-                            id: node_id,
-                            name: None,
-                            parent: None,
-                            attributes: HashMap::new(),
-                            children: Vec::new(),
-                        };
-                        node_stack.push(node);
-                    },
-                    b"children" => {
-                        // Children container, no action needed
-                    },
-                    _ => {}
-                }
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().unwrap_or(0);
+    let scale = exponent + 1;
+
+    let text = if scale > precision.max(digits.len()) as i32 || scale < -3 {
+        let fraction = if digits.len() > 1 { format!(".{}", &digits[1..]) } else { String::new() };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}{}E{}{:02}", &digits[..1], fraction, exponent_sign, exponent.abs())
+    } else if scale <= 0 {
+        format!("0.{}{}", "0".repeat(-scale as usize), digits)
+    } else if scale as usize >= digits.len() {
+        format!("{}{}", digits, "0".repeat(scale as usize - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..scale as usize], &digits[scale as usize..])
+    };
+    format!("{}{}", sign, text)
+}
+
+/// Add what LSLib writes for a TranslatedFSString to `element`: its embedded text, or the
+/// version when there is none, then the handle and the number of arguments.
+fn push_fs_string_fields(element: &mut Element, fs: &TranslatedFSString) {
+    match &fs.value {
+        Some(value) => element.push("value", value.as_str()),
+        None => element.push("version", fs.version.to_string()),
+    }
+    element.push("handle", fs.handle.as_str());
+    element.push("arguments", fs.arguments.len().to_string());
+}
+
+/// Write `element`, nesting `<arguments><argument key value><string .../></argument></arguments>` in it if there are arguments.
+fn write_fs_string_element<W: Write>(writer: &mut LsxOutput<W>, element: Element, arguments: &[TranslatedFSStringArgument]) -> Result<()> {
+    if arguments.is_empty() {
+        return writer.empty(&element);
+    }
+
+    writer.start(&element)?;
+    writer.start(&Element::new("arguments"))?;
+    for argument in arguments {
+        let mut argument_elem = Element::new("argument");
+        argument_elem.push("key", argument.key.as_str());
+        argument_elem.push("value", argument.value.as_str());
+        writer.start(&argument_elem)?;
+
+        let mut string_elem = Element::new("string");
+        push_fs_string_fields(&mut string_elem, &argument.string);
+        write_fs_string_element(writer, string_elem, &argument.string.arguments)?;
+
+        writer.end("argument")?;
+    }
+    writer.end("arguments")?;
+    writer.end(element.name)?;
+    Ok(())
+}
+
+/// Read an LSX file, returning the resource and the warnings collected along the way.
+/// In strict mode the first warning (a skipped attribute, a node outside of a region,
+/// a duplicate region or a region opened inside another) is returned as an error instead.
+pub fn read_lsx<P: AsRef<Path>>(path: P, options: &ReadOptions) -> Result<(Resource, ReadReport)> {
+    from_bytes(&std::fs::read(path)?, options)
+}
+
+/// Read an LSX document held in memory.
+pub fn from_bytes(data: &[u8], options: &ReadOptions) -> Result<(Resource, ReadReport)> {
+    LsxReader::new(data, options).read()
+}
+
+/// Read an LSX document from any buffered reader.
+/// The document is buffered first so that diagnostics can give its line and column.
+pub fn from_reader<R: BufRead>(mut reader: R, options: &ReadOptions) -> Result<(Resource, ReadReport)> {
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    from_bytes(&data, options)
+}
+
+/// LSX reading state. Errors and warnings are located with the line and column of the
+/// element being read, its region, the path of open nodes and the attribute id.
+struct LsxReader<'a> {
+    data: &'a [u8],
+    reader: Reader<&'a [u8]>,
+    report: ReadReport,
+    resource: Resource,
+    node_stack: Vec<Node>,
+    current_region: Option<Region>,
+    /// Id of the `<attribute>` being read
+    attribute: Option<String>,
+    /// Whether the document's GUIDs are byte-swapped, from `lslib_meta`
+    byte_swap_guids: bool,
+    /// Byte offset of the last element read
+    element_start: usize,
+}
+
+impl<'a> LsxReader<'a> {
+    fn new(data: &'a [u8], options: &ReadOptions) -> Self {
+        let mut reader = Reader::from_reader(data);
+        reader.trim_text(true);
+
+        Self {
+            data,
+            reader,
+            report: ReadReport::new(options),
+            resource: Resource {
+                // A document without `<version>` is taken to be BG3's, so it is written back with type names
+                metadata: Metadata {
+                    major_version: 4,
+                    minor_version: 0,
+                    revision: 0,
+                    build_number: 0,
+                },
+                regions: IndexMap::new(),
+                lsf_layout: None,
             },
-            Event::Empty(e) => {
-                match e.name().as_ref() {
-                    b"attribute" => {
-                        let mut attr_id = String::new();
-                        let mut attr_type = String::new();
-                        let mut attr_value = String::new();
-                        
-                        for attr in e.attributes() {
-                            let attr = attr?;
-                            match attr.key.as_ref() {
-                                b"id" => attr_id = String::from_utf8_lossy(&attr.value).to_string(),
-                                b"type" => attr_type = String::from_utf8_lossy(&attr.value).to_string(),
-                                b"value" => attr_value = String::from_utf8_lossy(&attr.value).to_string(),
-                                _ => {}
-                            }
-                        }
-                        
-                        if let Some(current_node) = node_stack.last_mut() {
-                            if let Some(parsed_type) = AttributeType::from_str(&attr_type) {
-                                let parsed_value = AttributeValue::from_string(&parsed_type, &attr_value)?;
-                                current_node.attributes.insert(attr_id, NodeAttribute {
-                                    attribute_type: parsed_type,
-                                    value: parsed_value,
-                                });
-                            }
-                        }
-                    },
-                    b"version" => {
-                        // Handle empty version element
-                        for attr in e.attributes() {
-                            let attr = attr?;
-                            match attr.key.as_ref() {
-                                b"major" => resource.metadata.major_version = parse_attr_value(&attr.value)?,
-                                b"minor" => resource.metadata.minor_version = parse_attr_value(&attr.value)?,
-                                b"revision" => resource.metadata.revision = parse_attr_value(&attr.value)?,
-                                b"build" => resource.metadata.build_number = parse_attr_value(&attr.value)?,
-                                _ => {}
-                            }
-                        }
-                    },
-                    _ => {}
-                }
+            node_stack: Vec::new(),
+            current_region: None,
+            attribute: None,
+            byte_swap_guids: true,
+            element_start: 0,
+        }
+    }
+
+    fn read(mut self) -> Result<(Resource, ReadReport)> {
+        loop {
+            match self.next_event()? {
+                Event::Start(e) => {
+                    match e.name().as_ref() {
+                        b"version" => self.read_version(&e)?,
+                        b"region" => self.start_region(&e)?,
+                        b"node" => self.start_node(&e)?,
+                        b"attribute" => {
+                            // Only TranslatedFSString attributes have content: their argument tree
+                            let fields = self.attribute_fields(&e)?;
+                            let start = self.element_start;
+                            self.attribute = Some(fields.id.clone());
+                            let arguments = self.read_fs_arguments(b"attribute", 0)?;
+                            self.element_start = start;
+                            self.add_attribute(fields, arguments)?;
+                        },
+                        _ => {}
+                    }
+                },
+                Event::Empty(e) => {
+                    match e.name().as_ref() {
+                        b"attribute" => {
+                            let fields = self.attribute_fields(&e)?;
+                            self.attribute = Some(fields.id.clone());
+                            self.add_attribute(fields, Vec::new())?;
+                        },
+                        b"version" => self.read_version(&e)?,
+                        b"node" => {
+                            // A node without attributes or children
+                            self.start_node(&e)?;
+                            self.end_node()?;
+                        },
+                        _ => {}
+                    }
+                },
+                Event::End(e) => {
+                    match e.name().as_ref() {
+                        b"node" => self.end_node()?,
+                        b"region" => self.end_region()?,
+                        b"save" => {
+                            // End of document
+                            break;
+                        },
+                        _ => {}
+                    }
+                },
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok((self.resource, self.report))
+    }
+
+    fn start_region(&mut self, element: &BytesStart) -> Result<()> {
+        let region_id = self.read_id(element)?.0;
+        if let Some(open) = &self.current_region {
+            // Keep what the open region has so far instead of dropping it
+            let diagnostic = self.diagnostic(format!("region '{}' opened inside region '{}'", region_id, open.name));
+            self.report.warn(diagnostic)?;
+            self.end_region()?;
+        }
+        self.current_region = Some(Region {
+            name: region_id,
+            nodes: Vec::new(),
+        });
+        Ok(())
+    }
+
+    fn start_node(&mut self, element: &BytesStart) -> Result<()> {
+        let (node_id, node_key) = self.read_id(element)?;
+        let node = Node {
+            id: node_id,
+            parent: self.node_stack.last().map(|parent| parent.id.clone()),
+            key: node_key,
+            attributes: IndexMap::new(),
+            children: Vec::new(),
+        };
+        self.node_stack.push(node);
+        Ok(())
+    }
+
+    fn end_node(&mut self) -> Result<()> {
+        if self.node_stack.len() == 1 && self.current_region.is_none() {
+            let diagnostic = self.diagnostic("node outside of a region");
+            self.node_stack.pop();
+            return self.report.warn(diagnostic);
+        }
+        if let Some(completed_node) = self.node_stack.pop() {
+            if let Some(parent_node) = self.node_stack.last_mut() {
+                // Add as child to parent node
+                parent_node.children.push(completed_node);
+            } else if let Some(region) = &mut self.current_region {
+                // Add as root node to current region
+                region.nodes.push(completed_node);
+            }
+        }
+        Ok(())
+    }
+
+    fn end_region(&mut self) -> Result<()> {
+        let Some(region) = &self.current_region else {
+            return Ok(());
+        };
+        if self.resource.regions.contains_key(&region.name) {
+            let diagnostic = self.diagnostic(format!("duplicate region '{}'", region.name));
+            self.current_region = None;
+            return self.report.warn(diagnostic);
+        }
+        if let Some(region) = self.current_region.take() {
+            self.resource.regions.insert(region.name.clone(), region);
+        }
+        Ok(())
+    }
+
+    /// Read the next event, remembering where it starts for diagnostics.
+    fn next_event(&mut self) -> Result<Event<'a>> {
+        let position = self.reader.buffer_position();
+        match self.reader.read_event() {
+            Ok(event) => {
+                // Whitespace between elements is trimmed, so the event starts at the first byte after it
+                let blank = self.data[position..].iter().take_while(|b| b.is_ascii_whitespace()).count();
+                self.element_start = position + blank;
+                Ok(event)
             },
-            Event::End(e) => {
-                match e.name().as_ref() {
-                    b"node" => {
-                        if let Some(completed_node) = node_stack.pop() {
-                            if let Some(parent_node) = node_stack.last_mut() {
-                                // Add as child to parent node
-                                parent_node.children.push(completed_node);
-                            } else if let Some(region) = &mut current_region {
-                                // Add as root node to current region
-                                region.nodes.push(completed_node);
-                            }
-                        }
-                    },
-                    b"region" => {
-                        if let Some(region) = current_region.take() {
-                            resource.regions.insert(region.name.clone(), region);
-                        }
-                    },
-                    b"children" => {
-                        // End of children container, no action needed
-                    },
-                    b"save" => {
-                        // End of document
-                        break;
-                    },
-                    _ => {}
-                }
+            Err(error) => {
+                self.element_start = self.reader.buffer_position();
+                Err(self.error(format!("malformed XML: {}", error)))
             },
-            Event::Eof => break,
-            _ => {}
         }
-        buf.clear();
     }
-    
-    Ok(resource)
+
+    /// A diagnostic for `message` at the current element.
+    fn diagnostic(&self, message: impl Into<String>) -> Diagnostic {
+        let (line, column) = line_column(self.data, self.element_start);
+        let mut diagnostic = Diagnostic::new(message).at_position(line, column);
+        if let Some(region) = &self.current_region {
+            diagnostic = diagnostic.in_region(region.name.as_str());
+        }
+        if !self.node_stack.is_empty() {
+            let path: Vec<&str> = self.node_stack.iter().map(|node| node.id.as_str()).collect();
+            diagnostic = diagnostic.at_node_path(path.join("/"));
+        }
+        if let Some(attribute) = &self.attribute {
+            diagnostic = diagnostic.at_attribute(attribute.as_str());
+        }
+        diagnostic
+    }
+
+    fn error(&self, message: impl Into<String>) -> anyhow::Error {
+        self.diagnostic(message).into()
+    }
+
+    fn read_version(&mut self, element: &BytesStart) -> Result<()> {
+        for attr in element.attributes() {
+            let attr = attr.map_err(|error| self.error(format!("malformed XML attribute: {}", error)))?;
+            let field = match attr.key.as_ref() {
+                b"lslib_meta" => {
+                    let meta = attr.unescape_value().map_err(|error| self.error(format!("malformed XML attribute: {}", error)))?;
+                    self.byte_swap_guids = swaps_guids(Some(&meta));
+                    continue;
+                },
+                b"major" => &mut self.resource.metadata.major_version,
+                b"minor" => &mut self.resource.metadata.minor_version,
+                b"revision" => &mut self.resource.metadata.revision,
+                b"build" => &mut self.resource.metadata.build_number,
+                _ => continue,
+            };
+            let text = String::from_utf8_lossy(&attr.value);
+            match text.parse() {
+                Ok(value) => *field = value,
+                Err(error) => return Err(self.error(format!(
+                    "invalid version {} '{}': {}", String::from_utf8_lossy(attr.key.as_ref()), text, error))),
+            }
+        }
+        Ok(())
+    }
+
+    /// The `id` and `key` of a `<region>` or `<node>` element.
+    fn read_id(&self, element: &BytesStart) -> Result<(String, Option<String>)> {
+        let fields = self.attribute_fields(element)?;
+        let key = (!fields.key.is_empty()).then_some(fields.key);
+        Ok((fields.id, key))
+    }
+
+    fn attribute_fields(&self, element: &BytesStart) -> Result<AttributeFields> {
+        AttributeFields::read(element).map_err(|error| self.error(format!("malformed XML attribute: {}", error)))
+    }
+
+    /// Convert an `<attribute>` element and add it to the innermost open node.
+    fn add_attribute(&mut self, fields: AttributeFields, arguments: Vec<TranslatedFSStringArgument>) -> Result<()> {
+        let result = self.convert_attribute(fields, arguments);
+        self.attribute = None;
+        result
+    }
+
+    fn convert_attribute(&mut self, fields: AttributeFields, arguments: Vec<TranslatedFSStringArgument>) -> Result<()> {
+        if self.node_stack.is_empty() {
+            let diagnostic = self.diagnostic("attribute outside of a node");
+            return self.report.warn(diagnostic);
+        }
+        let Some(attribute_type) = AttributeType::from_str(&fields.type_name) else {
+            let diagnostic = self.diagnostic(format!("unknown attribute type '{}'", fields.type_name));
+            return self.report.warn(diagnostic);
+        };
+
+        // A value that does not parse is an error in either mode: dropping it would lose data silently
+        let value = match attribute_type {
+            AttributeType::TranslatedString => fields.translated_string().map(AttributeValue::TranslatedString),
+            AttributeType::TranslatedFSString => fields.translated_fs_string(arguments).map(AttributeValue::TranslatedFSString),
+            _ => AttributeValue::from_string(&attribute_type, fields.value.as_deref().unwrap_or_default()),
+        }.map_err(|error| self.error(format!("invalid {} value: {}", fields.type_name, error)))?;
+        let value = match value {
+            AttributeValue::UUID(uuid) if !self.byte_swap_guids => AttributeValue::UUID(swap_guid_text_order(&uuid)),
+            value => value,
+        };
+        if let Some(current_node) = self.node_stack.last_mut() {
+            current_node.attributes.insert(fields.id, NodeAttribute { attribute_type, value });
+        }
+        Ok(())
+    }
+
+    /// Read the `<argument>` elements found before the end of the enclosing `end` element.
+    fn read_fs_arguments(&mut self, end: &[u8], depth: usize) -> Result<Vec<TranslatedFSStringArgument>> {
+        if depth > MAX_FS_STRING_DEPTH {
+            return Err(self.error(format!("TranslatedFSString arguments nested deeper than {}", MAX_FS_STRING_DEPTH)));
+        }
+
+        let mut arguments = Vec::new();
+        loop {
+            match self.next_event()? {
+                Event::Start(e) if e.name().as_ref() == b"argument" => {
+                    let fields = self.attribute_fields(&e)?;
+                    arguments.push(self.read_fs_argument(fields, depth)?);
+                },
+                Event::Empty(e) if e.name().as_ref() == b"argument" => {
+                    let fields = self.attribute_fields(&e)?;
+                    arguments.push(TranslatedFSStringArgument {
+                        key: fields.key,
+                        string: TranslatedFSString::default(),
+                        value: fields.value.unwrap_or_default(),
+                    });
+                },
+                Event::End(e) if e.name().as_ref() == end => break,
+                Event::Eof => return Err(self.error(format!("unexpected end of file inside <{}>", String::from_utf8_lossy(end)))),
+                _ => {}
+            }
+        }
+        Ok(arguments)
+    }
+
+    /// Read the `<string>` of an `<argument>` up to the argument's end.
+    fn read_fs_argument(&mut self, fields: AttributeFields, depth: usize) -> Result<TranslatedFSStringArgument> {
+        let mut string = TranslatedFSString::default();
+        loop {
+            match self.next_event()? {
+                Event::Start(e) if e.name().as_ref() == b"string" => {
+                    let string_fields = self.attribute_fields(&e)?;
+                    let start = self.element_start;
+                    let arguments = self.read_fs_arguments(b"string", depth + 1)?;
+                    self.element_start = start;
+                    string = string_fields.translated_fs_string(arguments)
+                        .map_err(|error| self.error(format!("invalid argument string: {}", error)))?;
+                },
+                Event::Empty(e) if e.name().as_ref() == b"string" => {
+                    string = self.attribute_fields(&e)?.translated_fs_string(Vec::new())
+                        .map_err(|error| self.error(format!("invalid argument string: {}", error)))?;
+                },
+                Event::End(e) if e.name().as_ref() == b"argument" => break,
+                Event::Eof => return Err(self.error("unexpected end of file inside <argument>")),
+                _ => {}
+            }
+        }
+
+        Ok(TranslatedFSStringArgument {
+            key: fields.key,
+            string,
+            value: fields.value.unwrap_or_default(),
+        })
+    }
+}
+
+/// 1-based line and column of byte `offset` in `data`, counting columns in characters.
+fn line_column(data: &[u8], offset: usize) -> (usize, usize) {
+    let before = &data[..offset.min(data.len())];
+    let line_start = before.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+    let line = before[..line_start].iter().filter(|&&b| b == b'\n').count() + 1;
+    let text = String::from_utf8_lossy(&before[line_start..]);
+    (line, text.trim_start_matches('\u{feff}').chars().count() + 1)
 }
 
-fn parse_attr_value(value: &[u8]) -> Result<u32> {
-    let value_str = String::from_utf8_lossy(value);
-    Ok(value_str.parse()?)
-}
\ No newline at end of file
+/// XML attributes of the `<attribute>`, `<argument>` and `<string>` elements.
+#[derive(Default)]
+struct AttributeFields {
+    id: String,
+    type_name: String,
+    key: String,
+    value: Option<String>,
+    handle: Option<String>,
+    version: Option<String>,
+}
+
+impl AttributeFields {
+    fn read(element: &BytesStart) -> quick_xml::Result<Self> {
+        let mut fields = Self::default();
+        for attr in element.attributes() {
+            let attr = attr?;
+            let text = attr.unescape_value()?.into_owned();
+            match attr.key.as_ref() {
+                b"id" => fields.id = text,
+                b"type" => fields.type_name = text,
+                b"key" => fields.key = text,
+                b"value" => fields.value = Some(text),
+                b"handle" => fields.handle = Some(text),
+                b"version" => fields.version = Some(text),
+                _ => {}
+            }
+        }
+        Ok(fields)
+    }
+
+    fn version(&self) -> Result<u16> {
+        Ok(self.version.as_deref().map(str::parse).transpose()?.unwrap_or(0))
+    }
+
+    fn translated_string(&self) -> Result<TranslatedString> {
+        Ok(TranslatedString {
+            version: self.version()?,
+            value: self.value.clone(),
+            handle: self.handle.clone().unwrap_or_default(),
+        })
+    }
+
+    fn translated_fs_string(&self, arguments: Vec<TranslatedFSStringArgument>) -> Result<TranslatedFSString> {
+        Ok(TranslatedFSString {
+            version: self.version()?,
+            value: self.value.clone(),
+            handle: self.handle.clone().unwrap_or_default(),
+            arguments,
+        })
+    }
+}
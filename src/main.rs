@@ -2,12 +2,10 @@ use clap::{Parser, Subcommand};
 use anyhow::Result;
 use std::path::PathBuf;
 
-mod lsf;
-mod lsx;
-mod resource;
-mod compression;
-
-use resource::Resource;
+use larian_converter::{lsf, lsx};
+use larian_converter::lsf::LsfVersion;
+use larian_converter::compression::{CompressionLevel, CompressionMethod};
+use larian_converter::diagnostics::{ReadOptions, ReadReport};
 
 #[derive(Parser)]
 #[command(name = "larian-convert")]
@@ -25,6 +23,18 @@ enum Commands {
         input: PathBuf,
         /// Output LSX file
         output: PathBuf,
+        /// Print warnings collected while reading the input
+        #[arg(long)]
+        report: bool,
+        /// Fail on the first skipped attribute instead of converting what can be read
+        #[arg(long)]
+        strict: bool,
+        /// Lay the output out exactly like LSLib: BOM, tabs, CRLF, .NET floats and lslib_meta
+        #[arg(long)]
+        lslib: bool,
+        /// Attribute type spelling: dos2 (numeric ids) or bg3 (names); follows the file's version by default
+        #[arg(long)]
+        dialect: Option<lsx::LsxDialect>,
     },
     /// Convert LSX (XML) to LSF (binary)
     ToBinary {
@@ -32,6 +42,26 @@ enum Commands {
         input: PathBuf,
         /// Output LSF file
         output: PathBuf,
+        /// LSF version to write, e.g. 3 for DOS2 or 7 for BG3
+        #[arg(long, default_value = "7")]
+        lsf_version: LsfVersion,
+        /// Chunk compression: none, zlib, lz4 or zstd
+        #[arg(long, default_value = "lz4")]
+        compression: CompressionMethod,
+        /// Compression level: fast, default or max
+        #[arg(long, default_value = "default")]
+        level: CompressionLevel,
+        /// Print warnings collected while reading the input
+        #[arg(long)]
+        report: bool,
+        /// Fail on the first skipped attribute instead of converting what can be read
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Check that an LSF file is written back unchanged after reading it
+    VerifyRoundtrip {
+        /// Input LSF file
+        input: PathBuf,
     },
 }
 
@@ -39,19 +69,41 @@ fn main() -> Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::ToXml { input, output } => {
+        Commands::ToXml { input, output, report, strict, lslib, dialect } => {
             println!("Converting {} to {}", input.display(), output.display());
-            let resource = lsf::read_lsf(&input)?;
-            lsx::write_lsx(&resource, &output)?;
+            let (resource, read_report) = lsf::read_lsf(&input, &ReadOptions { strict })?;
+            if report {
+                print_report(&read_report);
+            }
+            let mut options = if lslib { lsx::LsxWriteOptions::lslib() } else { lsx::LsxWriteOptions::default() };
+            options.dialect = dialect;
+            lsx::write_lsx(&resource, &output, &options)?;
             println!("Conversion completed successfully");
         }
-        Commands::ToBinary { input, output } => {
+        Commands::ToBinary { input, output, lsf_version, compression, level, report, strict } => {
             println!("Converting {} to {}", input.display(), output.display());
-            let resource = lsx::read_lsx(&input)?;
-            lsf::write_lsf(&resource, &output)?;
+            let (resource, read_report) = lsx::read_lsx(&input, &ReadOptions { strict })?;
+            if report {
+                print_report(&read_report);
+            }
+            lsf::write_lsf(&resource, &output, &lsf::WriteOptions { version: lsf_version, compression, level })?;
             println!("Conversion completed successfully");
         }
+        Commands::VerifyRoundtrip { input } => {
+            match lsf::verify_roundtrip(&input)? {
+                lsf::RoundTrip::Identical => println!("{}: round trip is byte-identical", input.display()),
+                lsf::RoundTrip::SameChunks => println!(
+                    "{}: round trip is identical after decompression (compressed bytes differ)", input.display()),
+            }
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+fn print_report(report: &ReadReport) {
+    for warning in &report.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    eprintln!("{} warning(s)", report.warnings.len());
+}
@@ -1,14 +1,59 @@
-use std::collections::HashMap;
-use serde::{Deserialize, Serialize};
+use indexmap::IndexMap;
+use std::fmt;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use uuid::Uuid;
 
+use crate::compression::{CompressionMethod, CompressionLevel};
+use crate::lsf::{LsfVersion, LsfMetadataFormat};
+
 #[derive(Debug, Clone)]
 pub struct Resource {
     pub metadata: Metadata,
-    pub regions: HashMap<String, Region>,
+    pub regions: IndexMap<String, Region>,
+    /// How the resource was stored on disk; `None` unless it was read from an LSF file.
+    pub lsf_layout: Option<LsfLayout>,
 }
 
-#[derive(Debug, Clone)]
+/// Storage details of an LSF file, kept so it can be written back the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsfLayout {
+    pub version: LsfVersion,
+    pub compression: CompressionMethod,
+    pub compression_level: CompressionLevel,
+    pub metadata_format: LsfMetadataFormat,
+    /// Chunk sizes on disk are filled in; LSLib leaves them 0 in uncompressed files
+    pub sizes_on_disk: bool,
+    /// The metadata byte and u16 after the compression flags, which LSLib leaves 0
+    pub unknown2: u8,
+    pub unknown3: u16,
+    /// The strings chunk as stored: every bucket's chain of names
+    pub string_buckets: Vec<Vec<String>>,
+    /// Node entries in file order
+    pub nodes: Vec<LsfNodeLayout>,
+}
+
+/// A node entry of an LSF file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsfNodeLayout {
+    pub name: String,
+    pub parent_index: i32,
+    /// -1 for the last sibling, and for every node of files without adjacency information
+    pub next_sibling_index: i32,
+    /// The node's attributes in chain order
+    pub attributes: Vec<LsfAttributeLayout>,
+}
+
+/// Where an attribute value was stored in the values chunk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LsfAttributeLayout {
+    pub name: String,
+    pub offset: u32,
+    /// Stored length, including any null padding of string values
+    pub length: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Metadata {
     pub major_version: u32,
     pub minor_version: u32,
@@ -24,14 +69,18 @@ pub struct Region {
 
 #[derive(Debug, Clone)]
 pub struct Node {
+    /// The node's name: `<node id>` in LSX, the node entry's name in LSF
     pub id: String,
-    pub name: Option<String>,
+    /// `id` of the parent node, or `None` for the root nodes of a region; set by the readers
+    /// and ignored by the writers, which follow `children`
     pub parent: Option<String>,
-    pub attributes: HashMap<String, NodeAttribute>,
+    /// Attribute the game uses to match this node against others when merging.
+    pub key: Option<String>,
+    pub attributes: IndexMap<String, NodeAttribute>,
     pub children: Vec<Node>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NodeAttribute {
     pub attribute_type: AttributeType,
     pub value: AttributeValue,
@@ -116,6 +165,26 @@ impl AttributeType {
         }
     }
 
+    /// Size in bytes of a value of this type in an LSF values chunk, or `None` for the
+    /// variable-length string, buffer and translated string types.
+    pub fn fixed_size(&self) -> Option<u32> {
+        match self {
+            Self::None => Some(0),
+            Self::Byte | Self::Bool | Self::Int8 => Some(1),
+            Self::Short | Self::UShort => Some(2),
+            Self::Int | Self::UInt | Self::Float => Some(4),
+            Self::Double | Self::ULongLong | Self::LongLong | Self::Int64 | Self::IVec2 | Self::Vec2 => Some(8),
+            Self::IVec3 | Self::Vec3 => Some(12),
+            Self::IVec4 | Self::Vec4 | Self::Mat2 | Self::UUID => Some(16),
+            Self::Mat3 => Some(36),
+            Self::Mat3x4 | Self::Mat4x3 => Some(48),
+            Self::Mat4 => Some(64),
+            Self::String | Self::Path | Self::FixedString | Self::LSString | Self::ScratchBuffer
+                | Self::TranslatedString | Self::WString | Self::LSWString | Self::TranslatedFSString => None,
+        }
+    }
+
+    /// The name LSLib writes in the `type` attribute of BG3 LSX files.
     pub fn as_str(&self) -> &'static str {
         match self {
             Self::None => "None",
@@ -132,19 +201,19 @@ impl AttributeType {
             Self::Vec2 => "fvec2",
             Self::Vec3 => "fvec3",
             Self::Vec4 => "fvec4",
-            Self::Mat2 => "mat2",
-            Self::Mat3 => "mat3",
+            Self::Mat2 => "mat2x2",
+            Self::Mat3 => "mat3x3",
             Self::Mat3x4 => "mat3x4",
             Self::Mat4x3 => "mat4x3",
-            Self::Mat4 => "mat4",
+            Self::Mat4 => "mat4x4",
             Self::Bool => "bool",
-            Self::String => "LSString",
+            Self::String => "string",
             Self::Path => "path",
             Self::FixedString => "FixedString",
             Self::LSString => "LSString",
             Self::ULongLong => "uint64",
             Self::ScratchBuffer => "ScratchBuffer",
-            Self::LongLong => "int64",
+            Self::LongLong => "old_int64",
             Self::Int8 => "int8",
             Self::TranslatedString => "TranslatedString",
             Self::WString => "WString",
@@ -155,7 +224,18 @@ impl AttributeType {
         }
     }
 
+    /// Parse a `type` attribute in any spelling LSX files have used: LSLib's BG3 names,
+    /// the numeric ids of DOS2-era files (`"22"` for FixedString) and the older
+    /// `mat2`/`mat3`/`mat4` matrix names.
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
+        // Ids are written without sign or leading zeros, so "+22" and "022" are not types
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            if s.len() > 1 && s.starts_with('0') {
+                return None;
+            }
+            return s.parse().ok().and_then(Self::from_u8);
+        }
         match s {
             "None" => Some(Self::None),
             "uint8" => Some(Self::Byte),
@@ -171,30 +251,32 @@ impl AttributeType {
             "fvec2" => Some(Self::Vec2),
             "fvec3" => Some(Self::Vec3),
             "fvec4" => Some(Self::Vec4),
-            "mat2" => Some(Self::Mat2),
-            "mat3" => Some(Self::Mat3),
+            "mat2x2" | "mat2" => Some(Self::Mat2),
+            "mat3x3" | "mat3" => Some(Self::Mat3),
             "mat3x4" => Some(Self::Mat3x4),
             "mat4x3" => Some(Self::Mat4x3),
-            "mat4" => Some(Self::Mat4),
+            "mat4x4" | "mat4" => Some(Self::Mat4),
             "bool" => Some(Self::Bool),
-            "LSString" => Some(Self::String),
+            "string" => Some(Self::String),
             "path" => Some(Self::Path),
             "FixedString" => Some(Self::FixedString),
+            "LSString" => Some(Self::LSString),
             "uint64" => Some(Self::ULongLong),
             "ScratchBuffer" => Some(Self::ScratchBuffer),
-            "int64" => Some(Self::LongLong),
+            "old_int64" => Some(Self::LongLong),
             "int8" => Some(Self::Int8),
             "TranslatedString" => Some(Self::TranslatedString),
             "WString" => Some(Self::WString),
             "LSWString" => Some(Self::LSWString),
             "guid" => Some(Self::UUID),
+            "int64" => Some(Self::Int64),
             "TranslatedFSString" => Some(Self::TranslatedFSString),
             _ => None,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AttributeValue {
     None,
     Byte(u8),
@@ -224,17 +306,44 @@ pub enum AttributeValue {
     ScratchBuffer(Vec<u8>),
     LongLong(i64),
     Int8(i8),
-    TranslatedString { value: String, handle: String },
+    TranslatedString(TranslatedString),
     WString(String),
     LSWString(String),
     UUID(Uuid),
     Int64(i64),
-    TranslatedFSString { value: String, handle: String },
+    TranslatedFSString(TranslatedFSString),
 }
 
-impl AttributeValue {
-    pub fn to_string(&self) -> String {
-        match self {
+/// Reference to a localized string.
+///
+/// Older files embed the text itself (`value`); since BG3 only a handle and a
+/// version are stored and the text is looked up in the localization files.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranslatedString {
+    pub version: u16,
+    pub value: Option<String>,
+    pub handle: String,
+}
+
+/// Localized format string whose placeholders are filled from `arguments`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranslatedFSString {
+    pub version: u16,
+    pub value: Option<String>,
+    pub handle: String,
+    pub arguments: Vec<TranslatedFSStringArgument>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranslatedFSStringArgument {
+    pub key: String,
+    pub string: TranslatedFSString,
+    pub value: String,
+}
+
+impl fmt::Display for AttributeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
             Self::None => String::new(),
             Self::Byte(v) => v.to_string(),
             Self::Short(v) => v.to_string(),
@@ -257,15 +366,20 @@ impl AttributeValue {
             Self::Bool(v) => if *v { "True".to_string() } else { "False".to_string() },
             Self::String(v) | Self::Path(v) | Self::FixedString(v) | Self::LSString(v) | Self::WString(v) | Self::LSWString(v) => v.clone(),
             Self::ULongLong(v) => v.to_string(),
-            Self::ScratchBuffer(v) => base64::encode(v),
+            Self::ScratchBuffer(v) => BASE64.encode(v),
             Self::LongLong(v) => v.to_string(),
             Self::Int8(v) => v.to_string(),
-            Self::TranslatedString { value, handle } => format!("{};{}", value, handle),
+            // The text when it is embedded, otherwise the handle; LSX stores both in attributes of their own
+            Self::TranslatedString(ts) => ts.value.clone().unwrap_or_else(|| ts.handle.clone()),
             Self::UUID(v) => v.to_string(),
             Self::Int64(v) => v.to_string(),
-            Self::TranslatedFSString { value, handle } => format!("{};{}", value, handle),
-        }
+            Self::TranslatedFSString(fs) => fs.value.clone().unwrap_or_else(|| fs.handle.clone()),
+        };
+        f.write_str(&s)
     }
+}
+
+impl AttributeValue {
 
     pub fn from_string(attr_type: &AttributeType, s: &str) -> anyhow::Result<Self> {
         Ok(match attr_type {
@@ -337,34 +451,25 @@ impl AttributeValue {
                 arr.copy_from_slice(&parts);
                 Self::Mat4(arr)
             },
-            AttributeType::Bool => Self::Bool(s == "True" || s == "true" || s == "1"),
-            AttributeType::String | AttributeType::LSString => Self::String(s.to_string()),
+            AttributeType::Bool => Self::Bool(match s {
+                "True" | "true" | "1" => true,
+                "False" | "false" | "0" => false,
+                _ => anyhow::bail!("invalid bool '{}'", s),
+            }),
+            AttributeType::String => Self::String(s.to_string()),
+            AttributeType::LSString => Self::LSString(s.to_string()),
             AttributeType::Path => Self::Path(s.to_string()),
             AttributeType::FixedString => Self::FixedString(s.to_string()),
             AttributeType::ULongLong => Self::ULongLong(s.parse()?),
-            AttributeType::ScratchBuffer => Self::ScratchBuffer(base64::decode(s)?),
+            AttributeType::ScratchBuffer => Self::ScratchBuffer(BASE64.decode(s)?),
             AttributeType::LongLong => Self::LongLong(s.parse()?),
             AttributeType::Int8 => Self::Int8(s.parse()?),
-            AttributeType::TranslatedString => {
-                let parts: Vec<&str> = s.splitn(2, ';').collect();
-                if parts.len() == 2 {
-                    Self::TranslatedString { value: parts[0].to_string(), handle: parts[1].to_string() }
-                } else {
-                    Self::TranslatedString { value: s.to_string(), handle: String::new() }
-                }
-            },
+            AttributeType::TranslatedString => anyhow::bail!("TranslatedString is stored as handle and version, not as a single string"),
             AttributeType::WString => Self::WString(s.to_string()),
             AttributeType::LSWString => Self::LSWString(s.to_string()),
             AttributeType::UUID => Self::UUID(Uuid::parse_str(s)?),
             AttributeType::Int64 => Self::Int64(s.parse()?),
-            AttributeType::TranslatedFSString => {
-                let parts: Vec<&str> = s.splitn(2, ';').collect();
-                if parts.len() == 2 {
-                    Self::TranslatedFSString { value: parts[0].to_string(), handle: parts[1].to_string() }
-                } else {
-                    Self::TranslatedFSString { value: s.to_string(), handle: String::new() }
-                }
-            },
+            AttributeType::TranslatedFSString => anyhow::bail!("TranslatedFSString is stored as handle, version and arguments, not as a single string"),
         })
     }
 }
\ No newline at end of file